@@ -0,0 +1,8 @@
+CREATE TABLE scan_failures (
+  id         SERIAL PRIMARY KEY,
+  path       VARCHAR UNIQUE NOT NULL,
+  phase      VARCHAR NOT NULL,
+  kind       VARCHAR NOT NULL,
+  message    VARCHAR NOT NULL,
+  failed_at  TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL
+);
@@ -0,0 +1 @@
+ALTER TABLE library ADD COLUMN indexed_hash BIGINT;
@@ -0,0 +1,3 @@
+DROP INDEX library_duration;
+
+ALTER TABLE library DROP COLUMN file_size;
@@ -0,0 +1,3 @@
+ALTER TABLE library ADD COLUMN file_size BIGINT;
+
+CREATE INDEX library_duration ON library (duration);
@@ -0,0 +1,9 @@
+CREATE TABLE fingerprints (
+  library_id   INTEGER PRIMARY KEY REFERENCES library(id) ON DELETE CASCADE,
+  fingerprint  VARCHAR NOT NULL,
+  raw          INTEGER[] NOT NULL,
+  duration     DOUBLE PRECISION NOT NULL,
+  algorithm    INTEGER NOT NULL,
+  version      VARCHAR NOT NULL,
+  mtime        TIMESTAMP WITH TIME ZONE NOT NULL
+);
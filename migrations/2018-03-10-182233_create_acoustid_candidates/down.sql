@@ -0,0 +1 @@
+DROP TABLE acoustid_candidates;
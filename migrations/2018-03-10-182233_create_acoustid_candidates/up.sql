@@ -0,0 +1,11 @@
+CREATE TABLE acoustid_candidates (
+  id            SERIAL PRIMARY KEY,
+  library_id    INTEGER NOT NULL REFERENCES library(id) ON DELETE CASCADE,
+  acoustid_id   VARCHAR NOT NULL,
+  score         REAL NOT NULL,
+  recording_id  UUID NOT NULL,
+  title         VARCHAR,
+  artists       VARCHAR,
+  duration      INTEGER,
+  UNIQUE (library_id, acoustid_id, recording_id)
+);
@@ -0,0 +1,9 @@
+CREATE TABLE acoustid_reviews (
+  library_id  INTEGER PRIMARY KEY REFERENCES library(id) ON DELETE CASCADE,
+  status      VARCHAR NOT NULL DEFAULT 'pending',
+  mbid        UUID,
+  created_at  TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL,
+  decided_at  TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX acoustid_reviews_status ON acoustid_reviews (status);
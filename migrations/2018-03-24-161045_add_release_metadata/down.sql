@@ -0,0 +1,11 @@
+ALTER TABLE acoustid_candidates DROP COLUMN release_date;
+ALTER TABLE acoustid_candidates DROP COLUMN track_position;
+ALTER TABLE acoustid_candidates DROP COLUMN medium_position;
+ALTER TABLE acoustid_candidates DROP COLUMN release_group_id;
+ALTER TABLE acoustid_candidates DROP COLUMN release_id;
+
+ALTER TABLE library DROP COLUMN release_date;
+ALTER TABLE library DROP COLUMN track_position;
+ALTER TABLE library DROP COLUMN medium_position;
+ALTER TABLE library DROP COLUMN release_group_id;
+ALTER TABLE library DROP COLUMN release_id;
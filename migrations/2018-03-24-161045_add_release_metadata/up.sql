@@ -0,0 +1,11 @@
+ALTER TABLE library ADD COLUMN release_id UUID;
+ALTER TABLE library ADD COLUMN release_group_id UUID;
+ALTER TABLE library ADD COLUMN medium_position INTEGER;
+ALTER TABLE library ADD COLUMN track_position INTEGER;
+ALTER TABLE library ADD COLUMN release_date VARCHAR;
+
+ALTER TABLE acoustid_candidates ADD COLUMN release_id UUID;
+ALTER TABLE acoustid_candidates ADD COLUMN release_group_id UUID;
+ALTER TABLE acoustid_candidates ADD COLUMN medium_position INTEGER;
+ALTER TABLE acoustid_candidates ADD COLUMN track_position INTEGER;
+ALTER TABLE acoustid_candidates ADD COLUMN release_date VARCHAR;
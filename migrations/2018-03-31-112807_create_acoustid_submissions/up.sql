@@ -0,0 +1,11 @@
+CREATE TABLE acoustid_submissions (
+  library_id     INTEGER PRIMARY KEY REFERENCES library(id) ON DELETE CASCADE,
+  submission_id  BIGINT NOT NULL,
+  mbid           UUID NOT NULL,
+  status         VARCHAR NOT NULL DEFAULT 'pending',
+  acoustid_id    VARCHAR,
+  submitted_at   TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL,
+  checked_at     TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX acoustid_submissions_status ON acoustid_submissions (status);
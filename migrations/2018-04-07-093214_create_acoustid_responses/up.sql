@@ -0,0 +1,7 @@
+CREATE TABLE acoustid_responses (
+  key         BIGINT PRIMARY KEY,
+  results     JSONB NOT NULL,
+  fetched_at  TIMESTAMP WITH TIME ZONE DEFAULT NOW() NOT NULL
+);
+
+CREATE INDEX acoustid_responses_fetched_at ON acoustid_responses (fetched_at);
@@ -0,0 +1,5 @@
+ALTER TABLE library DROP COLUMN fingerprint_mbid;
+ALTER TABLE library DROP COLUMN isrc;
+ALTER TABLE library DROP COLUMN tagged_release_group_id;
+ALTER TABLE library DROP COLUMN tagged_release_id;
+ALTER TABLE library DROP COLUMN tagged_mbid;
@@ -0,0 +1,11 @@
+ALTER TABLE library ADD COLUMN tagged_mbid UUID;
+ALTER TABLE library ADD COLUMN tagged_release_id UUID;
+ALTER TABLE library ADD COLUMN tagged_release_group_id UUID;
+ALTER TABLE library ADD COLUMN isrc VARCHAR;
+ALTER TABLE library ADD COLUMN fingerprint_mbid UUID;
+
+-- Every stored MusicBrainz ID came from AcoustID so far, except those typed
+-- in during a review
+UPDATE library SET fingerprint_mbid = mbid
+  WHERE mbid IS NOT NULL
+  AND id NOT IN (SELECT library_id FROM acoustid_reviews WHERE status = 'manual');
@@ -0,0 +1,11 @@
+ALTER TABLE library DROP COLUMN comment;
+ALTER TABLE library DROP COLUMN catalog_number;
+ALTER TABLE library DROP COLUMN label;
+ALTER TABLE library DROP COLUMN composer;
+ALTER TABLE library DROP COLUMN genres;
+ALTER TABLE library DROP COLUMN original_date;
+ALTER TABLE library DROP COLUMN date;
+ALTER TABLE library DROP COLUMN track_total;
+ALTER TABLE library DROP COLUMN disc_total;
+ALTER TABLE library DROP COLUMN disc_number;
+ALTER TABLE library DROP COLUMN album_artist;
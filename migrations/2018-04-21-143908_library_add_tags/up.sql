@@ -0,0 +1,11 @@
+ALTER TABLE library ADD COLUMN album_artist VARCHAR;
+ALTER TABLE library ADD COLUMN disc_number INTEGER;
+ALTER TABLE library ADD COLUMN disc_total INTEGER;
+ALTER TABLE library ADD COLUMN track_total INTEGER;
+ALTER TABLE library ADD COLUMN date VARCHAR;
+ALTER TABLE library ADD COLUMN original_date VARCHAR;
+ALTER TABLE library ADD COLUMN genres TEXT[] DEFAULT '{}' NOT NULL;
+ALTER TABLE library ADD COLUMN composer VARCHAR;
+ALTER TABLE library ADD COLUMN label VARCHAR;
+ALTER TABLE library ADD COLUMN catalog_number VARCHAR;
+ALTER TABLE library ADD COLUMN comment VARCHAR;
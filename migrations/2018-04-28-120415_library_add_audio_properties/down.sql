@@ -0,0 +1,12 @@
+ALTER TABLE library DROP COLUMN encoder_settings;
+ALTER TABLE library DROP COLUMN encoder;
+ALTER TABLE library DROP COLUMN channel_layout;
+ALTER TABLE library DROP COLUMN channels;
+ALTER TABLE library DROP COLUMN bit_depth;
+ALTER TABLE library DROP COLUMN sample_rate;
+ALTER TABLE library DROP COLUMN vbr;
+ALTER TABLE library DROP COLUMN bitrate;
+ALTER TABLE library DROP COLUMN lossless;
+ALTER TABLE library DROP COLUMN codec_profile;
+ALTER TABLE library DROP COLUMN codec;
+ALTER TABLE library DROP COLUMN container_format;
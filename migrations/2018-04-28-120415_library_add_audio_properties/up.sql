@@ -0,0 +1,12 @@
+ALTER TABLE library ADD COLUMN container_format VARCHAR;
+ALTER TABLE library ADD COLUMN codec VARCHAR;
+ALTER TABLE library ADD COLUMN codec_profile VARCHAR;
+ALTER TABLE library ADD COLUMN lossless BOOLEAN;
+ALTER TABLE library ADD COLUMN bitrate INTEGER;
+ALTER TABLE library ADD COLUMN vbr BOOLEAN;
+ALTER TABLE library ADD COLUMN sample_rate INTEGER;
+ALTER TABLE library ADD COLUMN bit_depth INTEGER;
+ALTER TABLE library ADD COLUMN channels INTEGER;
+ALTER TABLE library ADD COLUMN channel_layout VARCHAR;
+ALTER TABLE library ADD COLUMN encoder VARCHAR;
+ALTER TABLE library ADD COLUMN encoder_settings VARCHAR;
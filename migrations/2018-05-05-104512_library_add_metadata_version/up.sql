@@ -0,0 +1 @@
+ALTER TABLE library ADD COLUMN metadata_version INTEGER NOT NULL DEFAULT 0;
@@ -1,67 +1,377 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::cmp::Ordering;
-use std::thread;
+use std::io::{self, Write};
+use std::mem;
 use std::rc::Rc;
+use std::sync::Arc;
 use std::time::Duration;
 
+use chrono::{DateTime, Utc};
+use flate2::Compression;
+use flate2::write::GzEncoder;
 use futures::{Future, Stream};
 use futures::future::{self, Loop};
-use futures_cpupool::CpuPool;
-use hyper::Client;
-use hyper::client::HttpConnector;
-use hyper_tls::HttpsConnector;
-use ratelimit;
+use futures::sync::oneshot;
+use futures_cpupool::{Builder as CpuPoolBuilder, CpuPool};
+use hyper::{Client, Method, Request, Uri};
+use hyper::client::{HttpConnector, Service};
+use hyper::header::{ContentEncoding, ContentLength, ContentType, Encoding};
+use hyper_tls::{HttpsConnector, MaybeHttpsStream};
 use serde_json;
-use tokio_core::reactor::Handle;
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::{Core, Handle, Timeout};
 use uuid::Uuid;
 
+use config::{AcoustIdConfig, Config};
+use database::DatabaseConnection;
 use fingerprint;
+use rate_limiter::{RateLimitMetrics, RateLimiter};
+use response_cache::ResponseCache;
 
 use basic_types::*;
 
-static LOOKUP_URL: &'static str = "https://api.acoustid.org/v2/lookup";
+// Attempts at sending a batch when AcoustID is rate limiting or unavailable,
+// waiting twice as long before each retry
+static MAX_ATTEMPTS: u32 = 4;
 
-pub struct AcoustId {
+type LookupResult = Result<Vec<AcoustIdResult>, ProcessorError>;
+
+pub type AcoustIdClient = Client<AcoustIdConnector>;
+
+// Connector for `AcoustIdConfig::tls`. With TLS disabled no TLS connector is
+// set up at all and only plain HTTP URLs can be connected to, otherwise
+// plain HTTP URLs are still connected to without TLS.
+#[derive(Clone)]
+pub enum AcoustIdConnector {
+  Http(HttpConnector),
+  Https(HttpsConnector<HttpConnector>),
+}
+
+impl Service for AcoustIdConnector {
+  type Request = Uri;
+  type Response = MaybeHttpsStream<TcpStream>;
+  type Error = io::Error;
+  type Future = Box<Future<Item = MaybeHttpsStream<TcpStream>, Error = io::Error>>;
+
+  fn call(&self, uri: Uri) -> Self::Future {
+    match *self {
+      AcoustIdConnector::Http(ref http) => Box::new(http.call(uri).map(MaybeHttpsStream::Http)),
+      AcoustIdConnector::Https(ref https) => Box::new(https.call(uri)),
+    }
+  }
+}
+
+pub fn client(config: &AcoustIdConfig, threads: usize, handle: &Handle) -> AcoustIdClient {
+  let connector = if config.tls {
+    AcoustIdConnector::Https(HttpsConnector::new(threads, handle).unwrap())
+  } else {
+    AcoustIdConnector::Http(HttpConnector::new(threads, handle))
+  };
+
+  Client::configure()
+    .connector(connector)
+    .build(handle)
+}
+
+fn compress(body: &str) -> Result<Vec<u8>, ProcessorError> {
+  let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+  try!(encoder.write_all(body.as_bytes()));
+  let body = try!(encoder.finish());
+
+  Ok(body)
+}
+
+// POST request with a gzip compressed form body
+fn form_request(url: Uri, body: Vec<u8>) -> Request {
+  let mut req = Request::new(Method::Post, url);
+  req.headers_mut().set(ContentType::form_url_encoded());
+  req.headers_mut().set(ContentEncoding(vec![Encoding::Gzip]));
+  req.headers_mut().set(ContentLength(body.len() as u64));
+  req.set_body(body);
+
+  req
+}
+
+// Rate limit for every request to AcoustID, to be created once and shared
+// by everything in the process sending requests
+pub fn rate_limiter(config: &AcoustIdConfig, handle: &Handle) -> RateLimiter {
+  RateLimiter::new(config.requests_per_second, config.burst, handle)
+}
+
+// Look up a single fingerprint the way a scan does, through the response
+// cache and a batch request
+pub fn lookup_once(config: &Config, api_key: &str, duration: f64, fingerprint: &str) -> Result<Vec<AcoustIdResult>, ProcessorError> {
+  let mut core = try!(Core::new());
+  let database_pool = CpuPoolBuilder::new()
+    .pool_size(1)
+    .name_prefix("database_thread")
+    .create();
+
+  let conn = Arc::new(DatabaseConnection::new(database_pool, 1));
+  let cache = ResponseCache::new(conn, &config.acoustid);
+  let limiter = rate_limiter(&config.acoustid, &core.handle());
+  let acoustid = AcoustId::new(api_key.to_owned(), &config.acoustid, &limiter, cache, CpuPool::new(1), &core.handle());
+
+  core.run(acoustid.lookup_fingerprint(duration, fingerprint.to_owned()))
+}
+
+// A fingerprint to submit along with the recording it belongs to
+#[derive(Clone, Debug)]
+pub struct FingerprintSubmission {
+  pub duration: f64,
+  pub fingerprint: String,
+  pub mbid: Uuid,
+}
+
+// A fingerprint waiting to be sent with the next batch
+struct PendingLookup {
+  duration: f64,
+  fingerprint: String,
+  sender: oneshot::Sender<LookupResult>,
+}
+
+// Fingerprints waiting to be looked up, sent to AcoustID several at a time
+// in a single POST request
+struct LookupQueue {
   api_key: String,
-  client: Rc<Client<HttpsConnector<HttpConnector>>>,
-  ratelimit: Rc<RefCell<ratelimit::Handle>>,
+  meta: String,
+  lookup_url: Uri,
+  client: Rc<AcoustIdClient>,
+  limiter: RateLimiter,
+  handle: Handle,
+
+  batch_size: usize,
+  batch_wait: Duration,
+  retry_delay: Duration,
+
+  pending: RefCell<Vec<PendingLookup>>,
+
+  // Incremented every time a batch is sent, so the timer for a partial batch
+  // that filled up in the meantime does not send the next batch early
+  generation: Cell<u64>,
+}
+
+impl LookupQueue {
+  // Build the gzip compressed form body for a batch of fingerprints
+  fn batch_body(api_key: &str, meta: &str, lookups: &[PendingLookup]) -> Result<Vec<u8>, ProcessorError> {
+    let mut body = format!("format=json&client={}&meta={}", api_key, meta);
+    for (i, lookup) in lookups.iter().enumerate() {
+      body.push_str(&format!("&duration.{i}={duration:.0}&fingerprint.{i}={fingerprint}",
+        i=i,
+        duration=lookup.duration,
+        fingerprint=lookup.fingerprint
+      ));
+    }
+
+    compress(&body)
+  }
+
+  // Send a batch once, waiting for the rate limit first
+  fn attempt(&self, body: Vec<u8>, count: usize) -> impl Future<Item = Vec<LookupResult>, Error = ProcessorError> {
+    let client = Rc::clone(&self.client);
+    let req = form_request(self.lookup_url.clone(), body);
+
+    self.limiter.wait()
+      .and_then(move |_| client.request(req).map_err(ProcessorError::from))
+      .and_then(|res| {
+        let status = res.status().as_u16();
+
+        res.body()
+          .concat2()
+          .map_err(ProcessorError::from)
+          .map(move |body| (status, body))
+      })
+      .and_then(move |(status, body)| AcoustId::handle_batch_response(status, &body, count))
+  }
+
+  // Send every waiting fingerprint and hand each lookup its results
+  //
+  // The whole batch is retried with exponential backoff when AcoustID is
+  // rate limiting or unavailable.
+  fn send(queue: &Rc<Self>) -> Box<Future<Item = (), Error = ()>> {
+    queue.generation.set(queue.generation.get() + 1);
+
+    let lookups = mem::replace(&mut *queue.pending.borrow_mut(), Vec::new());
+    if lookups.is_empty() {
+      return Box::new(future::ok(()));
+    }
+
+    debug!("sending {} fingerprints to AcoustID", lookups.len());
+
+    let body = match Self::batch_body(&queue.api_key, &queue.meta, &lookups) {
+      Ok(v) => v,
+      Err(err) => {
+        Self::fail(lookups, &err);
+        return Box::new(future::ok(()));
+      },
+    };
+
+    let queue = Rc::clone(queue);
+    let count = lookups.len();
+
+    let future = future::loop_fn(0, move |attempt| {
+      let queue2 = Rc::clone(&queue);
+
+      queue.attempt(body.clone(), count)
+        .then(move |res| -> Box<Future<Item = Loop<Vec<LookupResult>, u32>, Error = ProcessorError>> {
+          match res {
+            Ok(results) => Box::new(future::ok(Loop::Break(results))),
+            Err(ref err) if err.is_transient() && attempt + 1 < MAX_ATTEMPTS => {
+              let delay = queue2.retry_delay * (1 << attempt);
+              warn!("AcoustID lookup failed, retrying in {:?}: {}", delay, err);
+
+              match Timeout::new(delay, &queue2.handle) {
+                Ok(timeout) => Box::new(
+                  timeout
+                    .map_err(ProcessorError::from)
+                    .map(move |_| Loop::Continue(attempt + 1))
+                ),
+                Err(err) => Box::new(future::err(ProcessorError::from(err))),
+              }
+            },
+            Err(err) => Box::new(future::err(err)),
+          }
+        })
+    })
+    .then(move |res| {
+      match res {
+        Ok(results) => {
+          for (lookup, result) in lookups.into_iter().zip(results) {
+            // The lookup may have been dropped in the meantime
+            let _ = lookup.sender.send(result);
+          }
+        },
+        Err(err) => Self::fail(lookups, &err),
+      };
+
+      Ok(())
+    });
+
+    Box::new(future)
+  }
+
+  // Queue a fingerprint for the next batch, resolving to its results
+  //
+  // The batch is sent once `batch_size` fingerprints are waiting or the
+  // first one has waited `batch_wait`.
+  fn enqueue(queue: &Rc<Self>, duration: f64, fingerprint: String) -> impl Future<Item = Vec<AcoustIdResult>, Error = ProcessorError> {
+    let (sender, receiver) = oneshot::channel();
+
+    let (first, full) = {
+      let mut pending = queue.pending.borrow_mut();
+      pending.push(PendingLookup { duration, fingerprint, sender });
+
+      (pending.len() == 1, pending.len() >= queue.batch_size)
+    };
+
+    if full {
+      queue.handle.spawn(Self::send(queue));
+    } else if first {
+      let queue2 = Rc::clone(queue);
+      let generation = queue.generation.get();
+
+      match Timeout::new(queue.batch_wait, &queue.handle) {
+        Ok(timeout) => queue.handle.spawn(timeout.then(move |_| -> Box<Future<Item = (), Error = ()>> {
+          if queue2.generation.get() == generation {
+            Self::send(&queue2)
+          } else {
+            Box::new(future::ok(()))
+          }
+        })),
+        Err(err) => {
+          error!("unable to create batch timer, sending now: {}", err);
+          queue.handle.spawn(Self::send(queue));
+        },
+      };
+    }
+
+    receiver
+      .then(|res| match res {
+        Ok(res) => res,
+        Err(_) => Err(ProcessorError::Thread("AcoustID lookup was dropped")),
+      })
+      .map_err(|e| e.in_phase(ScanPhase::AcoustId))
+  }
+
+  fn fail(lookups: Vec<PendingLookup>, err: &ProcessorError) {
+    error!("AcoustID batch lookup of {} fingerprints failed: {}", lookups.len(), err);
+
+    for lookup in lookups {
+      let _ = lookup.sender.send(Err(Self::copy_error(err)));
+    }
+  }
+
+  // Errors are not `Clone`, every lookup of a failed batch gets the AcoustID
+  // error itself or a description of any other error
+  fn copy_error(err: &ProcessorError) -> ProcessorError {
+    match *err.root() {
+      ProcessorError::AcoustIdApiKey(ref message) => ProcessorError::AcoustIdApiKey(message.clone()),
+      ProcessorError::AcoustIdRateLimited(ref message) => ProcessorError::AcoustIdRateLimited(message.clone()),
+      ProcessorError::AcoustIdUnavailable(ref message) => ProcessorError::AcoustIdUnavailable(message.clone()),
+      ProcessorError::AcoustIdRequest(code, ref message) => ProcessorError::AcoustIdRequest(code, message.clone()),
+      ref err => ProcessorError::from(io::Error::new(io::ErrorKind::Other, err.to_string())),
+    }
+  }
+}
+
+pub struct AcoustId {
+  queue: Rc<LookupQueue>,
+  cache: Option<Rc<ResponseCache>>,
 
   thread_pool: CpuPool,
 }
 
 impl AcoustId {
-  pub fn new(api_key: String, thread_pool: CpuPool, handle: &Handle) -> Self {
-    let mut limiter = ratelimit::Builder::new()
-      .capacity(3)
-      .quantum(3)
-      .interval(Duration::new(1, 0)) // 3 requests every 1 second
-      .build();
-    let limiter_handle = limiter.make_handle();
-
-    thread::Builder::new()
-      .name("ratelimit".into())
-      .spawn(move || limiter.run())
-      .unwrap();
-
-    let client = Client::configure()
-      .connector(HttpsConnector::new(4, handle).unwrap())
-      .build(handle);
+  pub fn new(
+    api_key: String,
+    config: &AcoustIdConfig,
+    limiter: &RateLimiter,
+    cache: Option<ResponseCache>,
+    thread_pool: CpuPool,
+    handle: &Handle
+  ) -> Self {
+    let queue = LookupQueue {
+      api_key,
+      meta: config.meta_param(),
+      lookup_url: config.lookup_url().parse().unwrap(),
+      client: Rc::new(client(config, 4, handle)),
+      limiter: limiter.clone(),
+      handle: handle.clone(),
+
+      batch_size: config.batch_size,
+      batch_wait: config.batch_wait(),
+      retry_delay: config.retry_delay(),
+
+      pending: RefCell::new(Vec::new()),
+      generation: Cell::new(0),
+    };
 
     Self {
-      api_key,
-      client: Rc::new(client),
-      ratelimit: Rc::new(RefCell::new(limiter_handle)),
+      queue: Rc::new(queue),
+      cache: cache.map(Rc::new),
 
       thread_pool,
     }
   }
 
-  fn handle_response(data: &[u8]) -> Result<AcoustIdResult, ProcessorError> {
-    let v: AcoustIdResponse = serde_json::from_slice(data)
-      .map_err(ProcessorError::from)?;
-    debug!("v: {:?}", v);
+  pub fn rate_limit_metrics(&self) -> RateLimitMetrics {
+    self.queue.limiter.metrics()
+  }
 
-    let mut results = try!(v.results.ok_or(ProcessorError::NoFingerprintMatch));
+  // Look up every fingerprint on AcoustID even if a response is cached,
+  // still caching the new responses
+  pub fn bypass_cache(&self) {
+    if let Some(ref cache) = self.cache {
+      cache.set_bypass(true);
+    }
+  }
+
+  // Best scoring first, no results at all counting as no match
+  fn sort_results(results: Option<Vec<AcoustIdResult>>) -> Result<Vec<AcoustIdResult>, ProcessorError> {
+    let mut results = try!(results.ok_or(ProcessorError::NoFingerprintMatch));
+    if results.is_empty() {
+      return Err(ProcessorError::NoFingerprintMatch);
+    }
 
     results.sort_by(|a, b| {
       if b.score > a.score {
@@ -73,79 +383,204 @@ impl AcoustId {
       }
     });
 
-    let first_result = try!(results.first().ok_or(ProcessorError::NoFingerprintMatch));
-    debug!("top result: {:?}", first_result);
+    debug!("top result: {:?}", results[0]);
 
-    Ok(first_result.clone())
+    Ok(results)
   }
 
-  pub fn lookup(
-    api_key: &str,
-    client: &Rc<Client<HttpsConnector<HttpConnector>>>,
-    duration: f64,
-    fingerprint: &str
-  ) -> impl Future<Item = AcoustIdResult, Error = ProcessorError> {
-    let url = format!("{base}?format=json&client={apiKey}&duration={duration:.0}&fingerprint={fingerprint}&meta=recordings",
-      base=LOOKUP_URL,
-      apiKey=api_key,
-      duration=duration,
-      fingerprint=fingerprint
-    ).parse().unwrap();
-
-    client.get(url)
-      .map_err(ProcessorError::from)
+  // Map an error object returned by the API to its error
+  fn api_error(code: i32, message: String) -> ProcessorError {
+    match code {
+      4 | 6 | 17 => ProcessorError::AcoustIdApiKey(message),
+      14         => ProcessorError::AcoustIdRateLimited(message),
+      5 | 13     => ProcessorError::AcoustIdUnavailable(message),
+      _          => ProcessorError::AcoustIdRequest(code, message),
+    }
+  }
+
+  // Turn an error response into its error. A body that is not JSON at all
+  // is judged by the HTTP status code instead.
+  fn check_response(status: u16, data: &[u8]) -> Result<(), ProcessorError> {
+    match serde_json::from_slice::<AcoustIdStatus>(data) {
+      Ok(ref v) if v.status == "ok" => Ok(()),
+      Ok(v) => Err(match v.error {
+        Some(err) => Self::api_error(err.code, err.message),
+        None => ProcessorError::AcoustIdRequest(0, format!("status: {}", v.status)),
+      }),
+      Err(_) if status == 429 => Err(ProcessorError::AcoustIdRateLimited(format!("HTTP {}", status))),
+      Err(_) if status >= 500 => Err(ProcessorError::AcoustIdUnavailable(format!("HTTP {}", status))),
+      Err(err) => Err(ProcessorError::from(err)),
+    }
+  }
+
+  // The results of each of the `count` fingerprints of a batch lookup, in
+  // the order they were sent
+  fn handle_batch_response(status: u16, data: &[u8], count: usize) -> Result<Vec<LookupResult>, ProcessorError> {
+    try!(Self::check_response(status, data));
+
+    let v: AcoustIdBatchResponse = serde_json::from_slice(data)
+      .map_err(ProcessorError::from)?;
+    debug!("v: {:?}", v);
+
+    // A successful batch without any results matched nothing
+    let fingerprints = v.fingerprints.unwrap_or_else(Vec::new);
+
+    let mut results: Vec<Option<Vec<AcoustIdResult>>> = vec![None; count];
+    for fingerprint in fingerprints {
+      // The index is sent back as it was in the request
+      let index = fingerprint.index.as_u64()
+        .or_else(|| fingerprint.index.as_str().and_then(|s| s.parse().ok()))
+        .map(|index| index as usize);
+
+      match index {
+        Some(index) if index < count => results[index] = fingerprint.results,
+        _ => warn!("AcoustID returned results for unknown index: {}", fingerprint.index),
+      };
+    }
+
+    Ok(results.into_iter().map(Self::sort_results).collect())
+  }
+
+  // Submissions in a response, ordered by their index in the request if
+  // they have one
+  fn handle_submission_response(status: u16, data: &[u8]) -> Result<Vec<AcoustIdSubmission>, ProcessorError> {
+    try!(Self::check_response(status, data));
+
+    let v: AcoustIdSubmissionResponse = serde_json::from_slice(data)
+      .map_err(ProcessorError::from)?;
+    debug!("v: {:?}", v);
+
+    let mut submissions = v.submissions.unwrap_or_else(Vec::new);
+    submissions.sort_by_key(|submission| submission.index());
+
+    Ok(submissions)
+  }
+
+  fn send_submission_request(
+    limiter: &RateLimiter,
+    client: &Rc<AcoustIdClient>,
+    req: Request
+  ) -> impl Future<Item = Vec<AcoustIdSubmission>, Error = ProcessorError> {
+    let client = Rc::clone(client);
+
+    limiter.wait()
+      .and_then(move |_| client.request(req).map_err(ProcessorError::from))
       .and_then(|res| {
+        let status = res.status().as_u16();
+
         res.body()
           .concat2()
           .map_err(ProcessorError::from)
-          .and_then(move |body| Self::handle_response(&body))
+          .and_then(move |body| Self::handle_submission_response(status, &body))
       })
   }
 
-  pub fn parse_file(&self, path: &str) -> impl Future<Item = Uuid, Error = ProcessorError> {
-    let api_key = self.api_key.clone();
-    let client = Rc::clone(&self.client);
-    let path = path.to_owned();
-    let ratelimit = self.ratelimit.borrow().clone();
+  // Submit fingerprints to AcoustID under the account of `user_key`,
+  // resolving to a submission for each, in the order they were given
+  pub fn submit(
+    api_key: &str,
+    user_key: &str,
+    config: &AcoustIdConfig,
+    limiter: &RateLimiter,
+    client: &Rc<AcoustIdClient>,
+    submissions: &[FingerprintSubmission]
+  ) -> Box<Future<Item = Vec<AcoustIdSubmission>, Error = ProcessorError>> {
+    let mut body = format!("format=json&client={}&user={}", api_key, user_key);
+    for (i, submission) in submissions.iter().enumerate() {
+      body.push_str(&format!("&duration.{i}={duration:.0}&fingerprint.{i}={fingerprint}&mbid.{i}={mbid}",
+        i=i,
+        duration=submission.duration,
+        fingerprint=submission.fingerprint,
+        mbid=submission.mbid
+      ));
+    }
 
-    let path2 = path.clone();
+    let body = match compress(&body) {
+      Ok(v) => v,
+      Err(err) => return Box::new(future::err(err)),
+    };
+    let req = form_request(config.submit_url().parse().unwrap(), body);
 
-    let ratelimit = future::loop_fn(ratelimit, |mut ratelimit| {
-      if ratelimit.try_wait().is_ok() {
-        Ok(Loop::Break(ratelimit))
-      } else {
-        Ok(Loop::Continue(ratelimit))
-      }
-    });
+    Box::new(Self::send_submission_request(limiter, client, req))
+  }
 
-    let fingerprint = self.thread_pool.spawn_fn(move || {
-      // Eat up fingerprinting errors, I mostly see them when a file is not easily
-      // parsed like WAV files
-      fingerprint::get(&path)
-    });
+  // Current status of earlier submissions
+  pub fn submission_status(
+    api_key: &str,
+    config: &AcoustIdConfig,
+    limiter: &RateLimiter,
+    client: &Rc<AcoustIdClient>,
+    ids: &[i64]
+  ) -> impl Future<Item = Vec<AcoustIdSubmission>, Error = ProcessorError> {
+    let mut url = format!("{}?format=json&client={}", config.submission_status_url(), api_key);
+    for id in ids {
+      url.push_str(&format!("&id={}", id));
+    }
 
-    fingerprint
-      .join(ratelimit)
-      .and_then(move |((duration, fingerprint), _)| {
-        Self::lookup(&api_key, &client, duration, &fingerprint)
-      })
-      .and_then(|result| {
-        let recordings = try!(result.recordings.ok_or(ProcessorError::NoFingerprintMatch));
-        let first = try!(recordings.first().ok_or(ProcessorError::NoFingerprintMatch));
+    let req = Request::new(Method::Get, url.parse().unwrap());
 
-        Ok(first.id)
-      })
-      .or_else(move |e| match e {
-        ProcessorError::NoAudioStream => {
-          error!("path: {}, weird case with no audio stream during fingerprinting (bad extension?)", path2);
-          Err(ProcessorError::NoFingerprintMatch)
-        },
-        ProcessorError::FFmpeg(e) => {
-          error!("path: {}, ffmpeg error: {}", path2, e);
-          Err(ProcessorError::NoFingerprintMatch)
-        },
-        _ => Err(e),
+    Self::send_submission_request(limiter, client, req)
+  }
+
+  // Get the fingerprint of an entry's file on the fingerprinting pool,
+  // reusing the stored fingerprint if it is still current
+  pub fn fingerprint_entry(&self, conn: &Arc<DatabaseConnection>, id: i32, path: &str, mtime: DateTime<Utc>) -> impl Future<Item = (f64, String), Error = ProcessorError> {
+    fingerprint::get_stored(&self.thread_pool, conn, id, path, mtime)
+  }
+
+  // Look up a fingerprint on AcoustID, resolving to every result
+  //
+  // A cached response for the fingerprint is used if there is one, any
+  // other lookup is cached once AcoustID answered.
+  pub fn lookup_fingerprint(&self, duration: f64, fingerprint: String) -> Box<Future<Item = Vec<AcoustIdResult>, Error = ProcessorError>> {
+    let cache = match self.cache {
+      Some(ref v) => Rc::clone(v),
+      None => return Box::new(LookupQueue::enqueue(&self.queue, duration, fingerprint)),
+    };
+    let queue = Rc::clone(&self.queue);
+
+    let future = cache.fetch(&fingerprint, duration)
+      .then(move |cached| -> Box<Future<Item = Vec<AcoustIdResult>, Error = ProcessorError>> {
+        match cached {
+          Ok(Some(results)) => {
+            debug!("using cached AcoustID response");
+            return Box::new(future::result(Self::sort_results(Some(results))));
+          },
+          Ok(None) => {},
+          Err(err) => warn!("unable to read cached AcoustID response: {}", err),
+        };
+
+        let fingerprint2 = fingerprint.clone();
+
+        let future = LookupQueue::enqueue(&queue, duration, fingerprint)
+          .then(move |res| -> Box<Future<Item = Vec<AcoustIdResult>, Error = ProcessorError>> {
+            // Not matching anything is an answer worth caching too
+            let results = match res {
+              Ok(ref results) => Some(results.clone()),
+              Err(ref err) => match *err.root() {
+                ProcessorError::NoFingerprintMatch => Some(Vec::new()),
+                _ => None,
+              },
+            };
+            let results = match results {
+              Some(v) => v,
+              None => return Box::new(future::result(res)),
+            };
+
+            Box::new(cache.store(&fingerprint2, duration, &results).then(move |stored| {
+              if let Err(err) = stored {
+                warn!("unable to cache AcoustID response: {}", err);
+              }
+
+              res
+            }))
+          });
+
+        Box::new(future)
       })
+      .map_err(|e| e.in_phase(ScanPhase::AcoustId));
+
+    Box::new(future)
   }
 }
 
@@ -153,31 +588,172 @@ impl AcoustId {
 mod tests {
   use super::*;
 
+  use test_server::{fixture, TestServer};
+
+  static FINGERPRINT: &'static str = "AQAAAoEA";
+
   #[test]
-  fn test_handle_response() {
+  fn test_handle_batch_response() {
     let json = r#"{
       "status": "ok",
-      "results": [
+      "fingerprints": [
+        {
+          "index": "2",
+          "results": [
+            { "score": 0.5, "id": "cd2e7c47-16f5-46c6-a37c-a1eb7bf599ff" },
+            { "score": 0.9, "id": "f2451269-9fec-4e82-aaf8-0bdf1f069ecf" }
+          ]
+        },
         {
-          "recordings": [
-            {
-              "title": "フラワリングナイト",
-              "id": "bdf27e74-cc62-43ae-8eb8-2b40d5c421a5",
-              "artists": [
-                {
-                  "id": "9f9a5476-22bd-48ef-8952-25cd8e3f1545",
-                  "name": "TAMUSIC"
-                }
-              ]
-            }
-          ],
-          "score": 0.999473,
-          "id": "f2451269-9fec-4e82-aaf8-0bdf1f069ecf"
+          "index": 0,
+          "results": []
         }
       ]
     }"#;
 
-    let first_result = AcoustId::handle_response(json.as_bytes()).unwrap();
-    assert_eq!(first_result.id, "f2451269-9fec-4e82-aaf8-0bdf1f069ecf");
+    let results = AcoustId::handle_batch_response(200, json.as_bytes(), 3).unwrap();
+    assert_eq!(results.len(), 3);
+
+    match results[0] {
+      Err(ProcessorError::NoFingerprintMatch) => {},
+      ref res => panic!("unexpected result: {:?}", res),
+    };
+    assert!(results[1].is_err());
+
+    let third = results[2].as_ref().unwrap();
+    assert_eq!(third[0].id, "f2451269-9fec-4e82-aaf8-0bdf1f069ecf");
+  }
+
+  #[test]
+  fn test_handle_response_errors() {
+    let json = r#"{"status": "error", "error": {"code": 4, "message": "invalid API key"}}"#;
+    match AcoustId::handle_batch_response(400, json.as_bytes(), 1) {
+      Err(ProcessorError::AcoustIdApiKey(ref message)) => assert_eq!(message, "invalid API key"),
+      res => panic!("unexpected result: {:?}", res),
+    };
+
+    let json = r#"{"status": "error", "error": {"code": 14, "message": "rate limit exceeded"}}"#;
+    let err = AcoustId::handle_batch_response(429, json.as_bytes(), 1).unwrap_err();
+    assert!(err.is_transient());
+
+    let err = AcoustId::handle_batch_response(503, b"<html>Service Unavailable</html>", 1).unwrap_err();
+    match err {
+      ProcessorError::AcoustIdUnavailable(_) => {},
+      err => panic!("unexpected error: {:?}", err),
+    };
+  }
+
+  #[test]
+  fn test_lookup_stand_in() {
+    let server = TestServer::start(vec![(200, fixture("lookup.json"))]);
+
+    let mut core = Core::new().unwrap();
+    let config = server.config(1);
+    let acoustid = AcoustId::new("key".to_owned(), &config, &rate_limiter(&config, &core.handle()), None, CpuPool::new(1), &core.handle());
+
+    let results = core.run(acoustid.lookup_fingerprint(215.0, FINGERPRINT.to_owned())).unwrap();
+    assert_eq!(results[0].id, "f2451269-9fec-4e82-aaf8-0bdf1f069ecf");
+
+    let recording = &results[0].recordings.as_ref().unwrap()[0];
+    let group = &recording.releasegroups.as_ref().unwrap()[0];
+    let release = &group.releases.as_ref().unwrap()[0];
+    assert_eq!(release.date.as_ref().and_then(|date| date.format()), Some("2011-08-13".to_owned()));
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].method, "POST");
+    assert_eq!(requests[0].path, "/v2/lookup");
+    assert!(requests[0].body.starts_with("format=json&client=key&meta=recordings+releasegroups+releases+tracks+compress"));
+  }
+
+  #[test]
+  fn test_batch_lookup_stand_in() {
+    let server = TestServer::start(vec![(200, fixture("batch.json"))]);
+
+    let mut core = Core::new().unwrap();
+    let config = server.config(2);
+    let acoustid = AcoustId::new("key".to_owned(), &config, &rate_limiter(&config, &core.handle()), None, CpuPool::new(1), &core.handle());
+
+    let first = acoustid.lookup_fingerprint(215.0, FINGERPRINT.to_owned());
+    let second = acoustid.lookup_fingerprint(180.0, FINGERPRINT.to_owned());
+
+    let (first, second) = core.run(first.then(Ok::<_, ()>).join(second.then(Ok))).unwrap();
+    assert_eq!(first.unwrap()[0].id, "f2451269-9fec-4e82-aaf8-0bdf1f069ecf");
+    match *second.unwrap_err().root() {
+      ProcessorError::NoFingerprintMatch => {},
+      ref err => panic!("unexpected error: {:?}", err),
+    };
+
+    // Both fingerprints went in a single request
+    let requests = server.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].method, "POST");
+    assert_eq!(requests[0].path, "/v2/lookup");
+    assert!(requests[0].body.contains("&duration.0=215&fingerprint.0=AQAAAoEA"));
+    assert!(requests[0].body.contains("&duration.1=180&fingerprint.1=AQAAAoEA"));
+  }
+
+  #[test]
+  fn test_batch_lookup_error_stand_in() {
+    let server = TestServer::start(vec![(400, fixture("invalid_api_key.json"))]);
+
+    let mut core = Core::new().unwrap();
+    let config = server.config(1);
+    let acoustid = AcoustId::new("key".to_owned(), &config, &rate_limiter(&config, &core.handle()), None, CpuPool::new(1), &core.handle());
+
+    let err = core.run(acoustid.lookup_fingerprint(215.0, FINGERPRINT.to_owned())).unwrap_err();
+    match *err.root() {
+      ProcessorError::AcoustIdApiKey(_) => {},
+      ref err => panic!("unexpected error: {:?}", err),
+    };
+  }
+
+  #[test]
+  fn test_batch_lookup_retry_stand_in() {
+    let server = TestServer::start(vec![
+      (503, fixture("service_unavailable.json")),
+      (200, fixture("batch.json")),
+    ]);
+
+    let mut core = Core::new().unwrap();
+    let config = AcoustIdConfig { retry_delay: 10, ..server.config(1) };
+    let acoustid = AcoustId::new("key".to_owned(), &config, &rate_limiter(&config, &core.handle()), None, CpuPool::new(1), &core.handle());
+
+    let results = core.run(acoustid.lookup_fingerprint(215.0, FINGERPRINT.to_owned())).unwrap();
+    assert_eq!(results[0].id, "f2451269-9fec-4e82-aaf8-0bdf1f069ecf");
+    assert_eq!(server.requests().len(), 2);
+  }
+
+  #[test]
+  fn test_submit_stand_in() {
+    let server = TestServer::start(vec![
+      (200, fixture("submit.json")),
+      (200, fixture("submission_status.json")),
+    ]);
+    let config = server.config(10);
+
+    let mut core = Core::new().unwrap();
+    let client = Rc::new(client(&config, 1, &core.handle()));
+    let limiter = rate_limiter(&config, &core.handle());
+
+    let mbid = Uuid::parse_str("bdf27e74-cc62-43ae-8eb8-2b40d5c421a5").unwrap();
+    let submissions = vec![
+      FingerprintSubmission { duration: 215.0, fingerprint: FINGERPRINT.to_owned(), mbid },
+      FingerprintSubmission { duration: 180.0, fingerprint: FINGERPRINT.to_owned(), mbid },
+    ];
+
+    let results = core.run(AcoustId::submit("key", "user", &config, &limiter, &client, &submissions)).unwrap();
+    assert_eq!(results.iter().map(|result| result.id).collect::<Vec<_>>(), vec![4171, 4172]);
+
+    let statuses = core.run(AcoustId::submission_status("key", &config, &limiter, &client, &[4171, 4172])).unwrap();
+    assert!(!statuses[0].is_pending());
+    assert_eq!(statuses[0].result.as_ref().unwrap().id, "f2451269-9fec-4e82-aaf8-0bdf1f069ecf");
+    assert!(statuses[1].is_pending());
+
+    let requests = server.requests();
+    assert_eq!(requests[0].path, "/v2/submit");
+    assert!(requests[0].body.contains("&user=user&"));
+    assert!(requests[0].body.contains("&mbid.1=bdf27e74-cc62-43ae-8eb8-2b40d5c421a5"));
+    assert_eq!(requests[1].path, "/v2/submission_status?format=json&client=key&id=4171&id=4172");
   }
 }
@@ -1,28 +1,92 @@
+use std::fmt;
 use std::io;
 
 use std::error::Error;
 
+use elastic;
 use ffmpeg;
 use hyper;
+use notify;
 use serde_json;
 
 use uuid::Uuid;
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct AcoustIdArtist {
   pub id: String,
   pub name: String,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+// Release date, any part of which can be unknown
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AcoustIdDate {
+  pub year: Option<i32>,
+  pub month: Option<u32>,
+  pub day: Option<u32>,
+}
+
+impl AcoustIdDate {
+  // As much of `YYYY-MM-DD` as is known
+  pub fn format(&self) -> Option<String> {
+    match (self.year, self.month, self.day) {
+      (Some(year), Some(month), Some(day)) => Some(format!("{:04}-{:02}-{:02}", year, month, day)),
+      (Some(year), Some(month), None)      => Some(format!("{:04}-{:02}", year, month)),
+      (Some(year), _, _)                   => Some(format!("{:04}", year)),
+      _ => None,
+    }
+  }
+}
+
+// A track of a medium, only the track of the matched recording is returned
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AcoustIdTrack {
+  pub id: Option<Uuid>,
+  pub position: Option<i32>,
+  pub title: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AcoustIdMedium {
+  pub position: Option<i32>,
+  pub format: Option<String>,
+  pub track_count: Option<i32>,
+  pub tracks: Option<Vec<AcoustIdTrack>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AcoustIdRelease {
+  pub id: Uuid,
+  pub title: Option<String>,
+  pub country: Option<String>,
+  pub date: Option<AcoustIdDate>,
+  pub mediums: Option<Vec<AcoustIdMedium>>,
+}
+
+// Release group of a recording, with its releases when `releases` meta was
+// requested along with `releasegroups`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AcoustIdReleaseGroup {
+  pub id: Uuid,
+  pub title: Option<String>,
+  #[serde(rename = "type")]
+  pub kind: Option<String>,
+  pub releases: Option<Vec<AcoustIdRelease>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct AcoustIdRecording {
   pub duration: Option<i32>,
   pub title: Option<String>,
   pub id: Uuid,
   pub artists: Option<Vec<AcoustIdArtist>>,
+
+  // Only one of these is returned, `releasegroups` whenever release groups
+  // were requested
+  pub releases: Option<Vec<AcoustIdRelease>>,
+  pub releasegroups: Option<Vec<AcoustIdReleaseGroup>>,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct AcoustIdResult {
   pub recordings: Option<Vec<AcoustIdRecording>>,
   pub score: f32,
@@ -30,20 +94,128 @@ pub struct AcoustIdResult {
 }
 
 #[derive(Debug, Deserialize)]
-pub struct AcoustIdResponse {
+pub struct AcoustIdApiError {
+  pub code: i32,
+  pub message: String,
+}
+
+// Status of any AcoustID response, with the error if the request failed
+#[derive(Debug, Deserialize)]
+pub struct AcoustIdStatus {
   pub status: String,
+  pub error: Option<AcoustIdApiError>,
+}
+
+// Results for one fingerprint of a batch lookup, `index` being the position
+// of the fingerprint in the request
+#[derive(Debug, Deserialize)]
+pub struct AcoustIdFingerprintResults {
+  pub index: serde_json::Value,
   pub results: Option<Vec<AcoustIdResult>>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct AcoustIdBatchResponse {
+  pub status: String,
+  pub fingerprints: Option<Vec<AcoustIdFingerprintResults>>,
+}
+
+// AcoustID track a submission was added to once it is imported
+#[derive(Clone, Debug, Deserialize)]
+pub struct AcoustIdSubmissionResult {
+  pub id: String,
+}
+
+// A submitted fingerprint, `index` being its position in the request when
+// it was just submitted
+#[derive(Clone, Debug, Deserialize)]
+pub struct AcoustIdSubmission {
+  pub index: Option<serde_json::Value>,
+  pub id: i64,
+  pub status: String,
+  pub result: Option<AcoustIdSubmissionResult>,
+}
+
+impl AcoustIdSubmission {
+  pub fn index(&self) -> Option<usize> {
+    self.index.as_ref().and_then(|index| {
+      index.as_u64().or_else(|| index.as_str().and_then(|s| s.parse().ok()))
+    }).map(|index| index as usize)
+  }
+
+  pub fn is_pending(&self) -> bool {
+    self.status == "pending"
+  }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AcoustIdSubmissionResponse {
+  pub status: String,
+  pub submissions: Option<Vec<AcoustIdSubmission>>,
+}
+
+// Phase of processing a file that an error happened in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScanPhase {
+  Metadata,
+  Database,
+  Fingerprint,
+  AcoustId,
+  Search,
+}
+
+impl ScanPhase {
+  pub fn as_str(&self) -> &'static str {
+    match *self {
+      ScanPhase::Metadata    => "metadata",
+      ScanPhase::Database    => "database",
+      ScanPhase::Fingerprint => "fingerprint",
+      ScanPhase::AcoustId    => "acoustid",
+      ScanPhase::Search      => "search",
+    }
+  }
+}
+
+impl fmt::Display for ScanPhase {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(self.as_str())
+  }
+}
+
 quick_error! {
   #[derive(Debug)]
   pub enum ProcessorError {
     NothingUseful {}
+    Unchanged {}
 
     ApiKey {}
     NoFingerprintMatch {}
     NoAudioStream {}
 
+    // A metadata reader was unable to open a file
+    Unreadable(reader: &'static str) {
+      display("{} could not open the file", reader)
+    }
+
+    // Errors returned by the AcoustID API
+    AcoustIdApiKey(message: String) {
+      display("invalid AcoustID API key: {}", message)
+    }
+    AcoustIdRateLimited(message: String) {
+      display("AcoustID rate limit exceeded: {}", message)
+    }
+    AcoustIdUnavailable(message: String) {
+      display("AcoustID service unavailable: {}", message)
+    }
+    AcoustIdRequest(code: i32, message: String) {
+      display("AcoustID error {}: {}", code, message)
+    }
+
+    Elastic(err: elastic::Error) {
+      from()
+      cause(err)
+      display(me) -> ("{}: {}", me.description(), err)
+    }
     HyperError(err: hyper::Error) {
       from()
       cause(err)
@@ -65,8 +237,80 @@ quick_error! {
       display(me) -> ("{} {}", me.description(), err)
     }
     Chromaprint(s: &'static str) {}
+    Notify(err: notify::Error) {
+      from()
+      cause(err)
+      display(me) -> ("{}: {}", me.description(), err)
+    }
 
     Thread(s: &'static str) {}
     Mutex(s: &'static str) {}
+
+    InPhase(phase: ScanPhase, err: Box<ProcessorError>) {
+      cause(&**err)
+      display("{} phase: {}", phase, err)
+    }
+  }
+}
+
+impl ProcessorError {
+  // Attach the phase of processing the error happened in. The innermost
+  // phase is kept if the error already has one.
+  pub fn in_phase(self, phase: ScanPhase) -> Self {
+    match self {
+      ProcessorError::InPhase(..) => self,
+      err => ProcessorError::InPhase(phase, Box::new(err)),
+    }
+  }
+
+  pub fn phase(&self) -> Option<ScanPhase> {
+    match *self {
+      ProcessorError::InPhase(phase, _) => Some(phase),
+      _ => None,
+    }
+  }
+
+  // The underlying error without any phase information
+  pub fn root(&self) -> &ProcessorError {
+    match *self {
+      ProcessorError::InPhase(_, ref err) => err.root(),
+      ref err => err,
+    }
+  }
+
+  // Whether the operation may succeed if tried again later
+  pub fn is_transient(&self) -> bool {
+    match *self.root() {
+      ProcessorError::AcoustIdRateLimited(_) |
+      ProcessorError::AcoustIdUnavailable(_) |
+      ProcessorError::HyperError(_)          => true,
+      _ => false,
+    }
+  }
+
+  // Short name for the kind of error, used when recording scan failures
+  pub fn kind(&self) -> &'static str {
+    match *self {
+      ProcessorError::NothingUseful          => "nothing_useful",
+      ProcessorError::Unchanged              => "unchanged",
+      ProcessorError::ApiKey                 => "api_key",
+      ProcessorError::NoFingerprintMatch     => "no_fingerprint_match",
+      ProcessorError::NoAudioStream          => "no_audio_stream",
+      ProcessorError::Unreadable(_)          => "unreadable",
+      ProcessorError::AcoustIdApiKey(_)      => "acoustid_api_key",
+      ProcessorError::AcoustIdRateLimited(_) => "acoustid_rate_limited",
+      ProcessorError::AcoustIdUnavailable(_) => "acoustid_unavailable",
+      ProcessorError::AcoustIdRequest(..)    => "acoustid_request",
+      ProcessorError::Elastic(_)             => "elastic",
+      ProcessorError::HyperError(_)          => "hyper",
+      ProcessorError::JsonError(_)           => "json",
+      ProcessorError::Io(_)                  => "io",
+      ProcessorError::FFmpeg(_)              => "ffmpeg",
+      ProcessorError::Chromaprint(_)         => "chromaprint",
+      ProcessorError::Notify(_)              => "notify",
+      ProcessorError::Thread(_)              => "thread",
+      ProcessorError::Mutex(_)               => "mutex",
+      ProcessorError::InPhase(_, ref err)    => err.kind(),
+    }
   }
 }
@@ -2,34 +2,33 @@ extern crate chrono;
 extern crate clap;
 extern crate dotenv;
 extern crate ffmpeg;
-extern crate hyper;
-extern crate hyper_tls;
 extern crate pretty_env_logger;
-extern crate ratelimit;
-extern crate tokio_core;
+extern crate uuid;
 
 #[macro_use] extern crate log;
 
 extern crate music_card_catalog;
 
 use std::env;
-use std::rc::Rc;
+use std::io::{self, Write};
 
 use clap::{App, Arg, SubCommand};
 use dotenv::dotenv;
-use hyper::Client;
-use hyper_tls::HttpsConnector;
-use tokio_core::reactor::Core;
+use uuid::Uuid;
 
-use music_card_catalog::acoustid::AcoustId;
+use music_card_catalog::acoustid;
+use music_card_catalog::duplicates;
 use music_card_catalog::elasticsearch::ElasticSearch;
 use music_card_catalog::fingerprint;
-use music_card_catalog::config::Config;
-use music_card_catalog::models::NewMediaFileInfo;
+use music_card_catalog::config::{Config, MetadataConfig};
+use music_card_catalog::metadata::MetadataReaders;
 use music_card_catalog::processor::Processor;
+use music_card_catalog::response_cache;
+use music_card_catalog::review::Reviewer;
+use music_card_catalog::submitter::Submitter;
 
-fn print_file_info(path: &str) {
-  let info = NewMediaFileInfo::read_file(path);
+fn print_file_info(config: &MetadataConfig, path: &str) {
+  let info = MetadataReaders::new(config).read(path);
 
   debug!("{:#?}", info);
 
@@ -39,30 +38,38 @@ fn print_file_info(path: &str) {
     println!("Artist: {}", info.artist.unwrap_or_else(String::new));
     println!("Album: {}", info.album.unwrap_or_else(String::new));
     println!("Track: {}", info.track.unwrap_or_else(String::new));
+    println!("Album Artist: {}", info.album_artist.unwrap_or_else(String::new));
     println!("Track Number: {}", info.track_number);
+    println!("Disc Number: {}", info.disc_number.map(|x| x.to_string()).unwrap_or_else(String::new));
+    println!("Date: {}", info.date.unwrap_or_else(String::new));
+    println!("Genres: {}", info.genres.join(", "));
+    println!("Composer: {}", info.composer.unwrap_or_else(String::new));
+    println!("Label: {}", info.label.unwrap_or_else(String::new));
+    println!("Catalog Number: {}", info.catalog_number.unwrap_or_else(String::new));
+    println!("MusicBrainz ID: {}", info.tagged_mbid.map(|x| x.to_string()).unwrap_or_else(String::new));
+    println!("ISRC: {}", info.isrc.unwrap_or_else(String::new));
     println!("Duration: {} ms", info.duration);
+    println!("Format: {}", info.container_format.unwrap_or_else(String::new));
+    println!("Codec: {} {}", info.codec.unwrap_or_else(String::new), info.codec_profile.unwrap_or_else(String::new));
+    println!("Lossless: {}", info.lossless.map(|x| x.to_string()).unwrap_or_else(String::new));
+    println!("Bitrate: {} b/s{}", info.bitrate.map(|x| x.to_string()).unwrap_or_else(String::new), if info.vbr == Some(true) { " (VBR)" } else { "" });
+    println!("Sample Rate: {} Hz", info.sample_rate.map(|x| x.to_string()).unwrap_or_else(String::new));
+    println!("Bit Depth: {}", info.bit_depth.map(|x| x.to_string()).unwrap_or_else(String::new));
+    println!("Channels: {} {}", info.channels.map(|x| x.to_string()).unwrap_or_else(String::new), info.channel_layout.unwrap_or_else(String::new));
+    println!("Encoder: {} {}", info.encoder.unwrap_or_else(String::new), info.encoder_settings.unwrap_or_else(String::new));
     println!("Modified Time: {}", info.mtime);
   } else {
     println!("No info could be gathered from the file");
   }
 }
 
-fn print_fingerprint(api_key: &str, lookup: bool, path: &str) {
+fn print_fingerprint(api_key: &str, config: &Config, lookup: bool, path: &str) {
   let (duration, fingerprint) = fingerprint::get(path).expect("Error getting file's fingerprint");
 
   println!("{}", fingerprint);
 
   if lookup {
-    let mut core = Core::new().unwrap();
-
-    let client = Client::configure()
-      .connector(HttpsConnector::new(1, &core.handle()).unwrap())
-      .build(&core.handle());
-    let client = Rc::new(client);
-
-    let future = AcoustId::lookup(api_key, &client, duration, &fingerprint);
-
-    match core.run(future) {
+    match acoustid::lookup_once(config, api_key, duration, &fingerprint) {
       Ok(res) => {
         println!("Result: {:#?}", res);
       },
@@ -73,6 +80,84 @@ fn print_fingerprint(api_key: &str, lookup: bool, path: &str) {
   }
 }
 
+fn prompt(message: &str) -> Option<String> {
+  print!("{}", message);
+  io::stdout().flush().ok();
+
+  let mut line = String::new();
+  match io::stdin().read_line(&mut line) {
+    Ok(0) | Err(_) => None,
+    Ok(_) => Some(line.trim().to_owned()),
+  }
+}
+
+fn review_files(config: &Config) {
+  let mut reviewer = Reviewer::new(config).expect("Failed to set up review");
+  let pending = reviewer.pending().expect("Error loading pending reviews");
+
+  println!("{} files to review", pending.len());
+
+  for info in pending {
+    let candidates = reviewer.candidates(info.id).expect("Error loading AcoustID candidates");
+
+    println!("");
+    println!("Path: {}", info.path);
+    println!("  Title: {}", info.title.as_ref().map(|s| s.as_str()).unwrap_or(""));
+    println!("  Artist: {}", info.artist.as_ref().map(|s| s.as_str()).unwrap_or(""));
+    println!("  Album: {}", info.album.as_ref().map(|s| s.as_str()).unwrap_or(""));
+    println!("  Duration: {} s", info.duration / 1000);
+    println!("Candidates:");
+
+    for (i, candidate) in candidates.iter().enumerate() {
+      println!("  [{}] {:.3} {} - {} ({} s) {}",
+        i + 1,
+        candidate.score,
+        candidate.artists.as_ref().map(|s| s.as_str()).unwrap_or("?"),
+        candidate.title.as_ref().map(|s| s.as_str()).unwrap_or("?"),
+        candidate.duration.map(|d| d.to_string()).unwrap_or_else(|| "?".to_owned()),
+        candidate.recording_id);
+    }
+
+    loop {
+      let answer = match prompt("Accept [number], (r)eject, (m)anual MBID, (s)kip, (q)uit: ") {
+        Some(v) => v,
+        None => return,
+      };
+
+      let res = match answer.as_str() {
+        "r" => reviewer.reject(info.id),
+        "m" => {
+          let mbid = match prompt("MBID: ").map(|s| Uuid::parse_str(&s)) {
+            Some(Ok(v)) => v,
+            Some(Err(_)) => {
+              println!("Not a valid MBID");
+              continue;
+            },
+            None => return,
+          };
+
+          reviewer.set_manual(info.id, mbid)
+        },
+        "s" => break,
+        "q" => return,
+        number => match number.parse::<usize>().ok().and_then(|n| n.checked_sub(1)).and_then(|i| candidates.get(i)) {
+          Some(candidate) => reviewer.accept(info.id, candidate.recording_id),
+          None => {
+            println!("Unknown choice");
+            continue;
+          },
+        },
+      };
+
+      if let Err(err) = res {
+        panic!("error saving review for path: {}, {:#?}", info.path, err);
+      }
+
+      break;
+    }
+  }
+}
+
 // Main entrypoint for the program
 fn main() {
   // Initialize libraries
@@ -87,15 +172,57 @@ fn main() {
   ffmpeg::init().unwrap();
 
   log::set_max_level(log::LevelFilter::Debug);
+
+  let no_cache = Arg::with_name("no-cache")
+    .help("look up every file on AcoustID instead of using cached responses")
+    .long("no-cache");
+
   let matches = App::new("Music Card Catalog")
     .version("0.1.0")
     .author("Matt Bilker <me@mbilker.us>")
     .about("Gather data about my music library")
     .subcommand(SubCommand::with_name("scan")
       .about("scan music library directories")
-      .author("Matt Bilker <me@mbilker.us>"))
+      .author("Matt Bilker <me@mbilker.us>")
+      .arg(no_cache.clone()))
+    .subcommand(SubCommand::with_name("retry-failures")
+      .about("reprocess files that failed during a previous scan")
+      .author("Matt Bilker <me@mbilker.us>")
+      .arg(no_cache.clone()))
     .subcommand(SubCommand::with_name("prune")
       .about("prune database of non-existant files")
+      .author("Matt Bilker <me@mbilker.us>")
+      .arg(Arg::with_name("reconcile")
+        .help("also remove search documents without a library entry")
+        .short("r")
+        .long("reconcile")))
+    .subcommand(SubCommand::with_name("watch")
+      .about("process changes to music library directories as they happen")
+      .author("Matt Bilker <me@mbilker.us>")
+      .arg(no_cache))
+    .subcommand(SubCommand::with_name("reindex")
+      .about("rebuild the search index from the database")
+      .author("Matt Bilker <me@mbilker.us>"))
+    .subcommand(SubCommand::with_name("review")
+      .about("decide on files without a clear AcoustID match")
+      .author("Matt Bilker <me@mbilker.us>"))
+    .subcommand(SubCommand::with_name("mismatches")
+      .about("list files tagged with a different recording than AcoustID matched")
+      .author("Matt Bilker <me@mbilker.us>"))
+    .subcommand(SubCommand::with_name("dupes")
+      .about("find copies of the same recording by comparing fingerprints")
+      .author("Matt Bilker <me@mbilker.us>")
+      .arg(Arg::with_name("threshold")
+        .help("highest fraction of differing fingerprint bits between copies")
+        .short("t")
+        .long("threshold")
+        .takes_value(true)
+        .default_value("0.15")))
+    .subcommand(SubCommand::with_name("submit")
+      .about("submit fingerprints of tagged files AcoustID does not know")
+      .author("Matt Bilker <me@mbilker.us>"))
+    .subcommand(SubCommand::with_name("purge-cache")
+      .about("delete cached AcoustID responses older than the configured TTL")
       .author("Matt Bilker <me@mbilker.us>"))
     .subcommand(SubCommand::with_name("info")
       .about("show info about a single file")
@@ -125,31 +252,123 @@ fn main() {
   };
   println!("Config: {:?}", config);
 
-  if let Some(_matches) = matches.subcommand_matches("scan") {
+  if let Some(matches) = matches.subcommand_matches("scan") {
     let mut processor = Processor::new(&config);
+    if matches.is_present("no-cache") {
+      processor.bypass_cache();
+    }
 
-    let res = processor.scan_dirs();
-    if let Err(err) = res {
-      panic!("error scannning directories: {:#?}", err);
+    match processor.scan_dirs() {
+      Ok(summary) => summary.print(),
+      Err(err) => panic!("error scannning directories: {:#?}", err),
+    };
+  } else if let Some(matches) = matches.subcommand_matches("retry-failures") {
+    let mut processor = Processor::new(&config);
+    if matches.is_present("no-cache") {
+      processor.bypass_cache();
     }
-  } else if let Some(_matches) = matches.subcommand_matches("prune") {
+
+    match processor.retry_failures() {
+      Ok(summary) => summary.print(),
+      Err(err) => panic!("error retrying failed files: {:#?}", err),
+    };
+  } else if let Some(matches) = matches.subcommand_matches("prune") {
     let mut processor = Processor::new(&config);
 
     let res = processor.prune_db();
     if let Err(err) = res {
       panic!("error pruning database: {:#?}", err);
     }
+
+    if matches.is_present("reconcile") {
+      let res = processor.reconcile_search();
+      if let Err(err) = res {
+        panic!("error reconciling search index: {:#?}", err);
+      }
+    }
+  } else if let Some(matches) = matches.subcommand_matches("watch") {
+    let mut processor = Processor::new(&config);
+    if matches.is_present("no-cache") {
+      processor.bypass_cache();
+    }
+
+    let res = processor.watch();
+    if let Err(err) = res {
+      panic!("error watching directories: {:#?}", err);
+    }
+  } else if let Some(_matches) = matches.subcommand_matches("reindex") {
+    let mut processor = Processor::new(&config);
+
+    let res = processor.reindex();
+    if let Err(err) = res {
+      panic!("error rebuilding search index: {:#?}", err);
+    }
+  } else if let Some(_matches) = matches.subcommand_matches("review") {
+    review_files(&config);
+  } else if let Some(_matches) = matches.subcommand_matches("mismatches") {
+    let mut reviewer = Reviewer::new(&config).expect("Failed to set up review");
+    let files = match reviewer.disagreements() {
+      Ok(v) => v,
+      Err(err) => panic!("error loading MusicBrainz ID disagreements: {:#?}", err),
+    };
+
+    println!("{} files are tagged with a different recording than AcoustID matched", files.len());
+
+    for info in files {
+      println!("");
+      println!("Path: {}", info.path);
+      println!("  Tagged: {}", info.tagged_mbid.map(|x| x.to_string()).unwrap_or_default());
+      println!("  AcoustID: {}", info.fingerprint_mbid.map(|x| x.to_string()).unwrap_or_default());
+    }
+  } else if let Some(matches) = matches.subcommand_matches("dupes") {
+    let threshold: f64 = matches.value_of("threshold").unwrap().parse().expect("threshold must be a number");
+
+    let clusters = match duplicates::find_duplicates(&config, threshold) {
+      Ok(v) => v,
+      Err(err) => panic!("error finding duplicates: {:#?}", err),
+    };
+
+    println!("Found {} sets of duplicates", clusters.len());
+
+    for (i, cluster) in clusters.iter().enumerate() {
+      println!("");
+      println!("Set {} ({} files):", i + 1, cluster.len());
+
+      for file in cluster {
+        println!("  {}, {}, {}",
+          file.format.as_ref().map(|s| s.as_str()).unwrap_or("unknown format"),
+          file.bitrate.as_ref().map(|s| s.as_str()).unwrap_or("unknown bitrate"),
+          file.path);
+      }
+    }
+  } else if let Some(_matches) = matches.subcommand_matches("submit") {
+    let api_key = config.api_keys.get("acoustid").expect("No AcoustID API key defined in config.yaml");
+    let user_key = config.api_keys.get("acoustid_user").expect("No AcoustID user API key defined in config.yaml");
+
+    let mut submitter = Submitter::new(&config, api_key, user_key).expect("Failed to set up submission");
+    let summary = match submitter.run() {
+      Ok(v) => v,
+      Err(err) => panic!("error submitting fingerprints: {:#?}", err),
+    };
+
+    println!("Submitted {} fingerprints, {} imported, {} still pending", summary.submitted, summary.imported, summary.pending);
+    println!("{} files have no MusicBrainz ID tag", summary.untagged);
+  } else if let Some(_matches) = matches.subcommand_matches("purge-cache") {
+    match response_cache::purge_stale(&config) {
+      Ok(count) => println!("Purged {} cached AcoustID responses", count),
+      Err(err) => panic!("error purging cached AcoustID responses: {:#?}", err),
+    };
   } else if let Some(matches) = matches.subcommand_matches("info") {
     let file_path = matches.value_of("path").unwrap();
 
-    print_file_info(file_path);
+    print_file_info(&config.metadata, file_path);
   } else if let Some(matches) = matches.subcommand_matches("fingerprint") {
     let file_path = matches.value_of("path").unwrap();
 
     let lookup = matches.is_present("lookup");
     let api_key = config.api_keys.get("acoustid").expect("No AcoustID API key defined in config.yaml");
 
-    print_fingerprint(api_key, lookup, file_path);
+    print_fingerprint(api_key, &config, lookup, file_path);
   } else if let Some(_matches) = matches.subcommand_matches("dump") {
     println!("Elasticsearch mapping: {:#?}", ElasticSearch::body());
   }
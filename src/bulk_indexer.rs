@@ -0,0 +1,144 @@
+use std::cell::{Cell, RefCell};
+use std::mem;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::Future;
+use futures::future;
+
+use elasticsearch::ElasticSearch;
+use models::MediaFileInfoDocument;
+
+use basic_types::*;
+
+// A document the bulk API refused to index
+#[derive(Clone, Debug)]
+pub struct BulkItemFailure {
+  pub id: i32,
+  pub path: String,
+  pub message: String,
+}
+
+// Outcome of sending a batch of documents
+#[derive(Debug, Default)]
+pub struct BulkFlush {
+  // Id and content hash of every document that was indexed
+  pub indexed: Vec<(i32, i64)>,
+  pub failures: Vec<BulkItemFailure>,
+}
+
+// Collects documents and sends them to Elasticsearch in batches through the
+// bulk API instead of one request per document
+//
+// A batch is sent once `batch_size` documents are waiting, or by
+// `flush_stale` once the oldest batch has waited longer than the flush
+// interval. Anything left over must be sent with `flush` when done.
+pub struct BulkIndexer {
+  search: Arc<ElasticSearch>,
+
+  pending: RefCell<Vec<MediaFileInfoDocument>>,
+  last_flush: Cell<Instant>,
+
+  batch_size: usize,
+  flush_interval: Duration,
+}
+
+impl BulkIndexer {
+  pub fn new(search: &Arc<ElasticSearch>, batch_size: usize, flush_interval: Duration) -> Rc<Self> {
+    let search = Arc::clone(search);
+
+    Rc::new(Self {
+      search,
+
+      pending: RefCell::new(Vec::with_capacity(batch_size)),
+      last_flush: Cell::new(Instant::now()),
+
+      batch_size,
+      flush_interval,
+    })
+  }
+
+  // Queue a document, sending the batch if it is full
+  pub fn push(&self, doc: MediaFileInfoDocument) -> Box<Future<Item = BulkFlush, Error = ProcessorError>> {
+    let full = {
+      let mut pending = self.pending.borrow_mut();
+      pending.push(doc);
+
+      pending.len() >= self.batch_size
+    };
+
+    if full {
+      self.flush()
+    } else {
+      Box::new(future::ok(BulkFlush::default()))
+    }
+  }
+
+  // Send the waiting documents if they have waited longer than the flush
+  // interval
+  pub fn flush_stale(&self) -> Box<Future<Item = BulkFlush, Error = ProcessorError>> {
+    if self.last_flush.get().elapsed() >= self.flush_interval {
+      self.flush()
+    } else {
+      Box::new(future::ok(BulkFlush::default()))
+    }
+  }
+
+  // Send every waiting document
+  //
+  // Failures are reported per document, including when the whole request
+  // fails, so the future itself only resolves successfully.
+  pub fn flush(&self) -> Box<Future<Item = BulkFlush, Error = ProcessorError>> {
+    self.last_flush.set(Instant::now());
+
+    let docs = mem::replace(&mut *self.pending.borrow_mut(), Vec::with_capacity(self.batch_size));
+    if docs.is_empty() {
+      return Box::new(future::ok(BulkFlush::default()));
+    }
+
+    debug!("sending {} documents to the bulk API", docs.len());
+
+    let future = self.search.bulk_insert_documents(&docs)
+      .then(move |res| {
+        let failures: Vec<BulkItemFailure> = match res {
+          Ok(res) => {
+            trace!("elastic bulk res: {:?}", res);
+
+            ElasticSearch::bulk_item_errors(&res)
+              .into_iter()
+              .map(|(id, message)| {
+                let path = docs.iter()
+                  .find(|doc| doc.id == id)
+                  .map(|doc| doc.path.clone())
+                  .unwrap_or_else(String::new);
+
+                BulkItemFailure { id, path, message }
+              })
+              .collect()
+          },
+          Err(err) => {
+            error!("elastic bulk error: {:#?}", err);
+
+            let message = err.to_string();
+            docs.iter()
+              .map(|doc| BulkItemFailure {
+                id: doc.id,
+                path: doc.path.clone(),
+                message: message.clone(),
+              })
+              .collect()
+          },
+        };
+
+        let indexed = docs.iter()
+          .filter(|doc| !failures.iter().any(|failure| failure.id == doc.id))
+          .map(|doc| (doc.id, doc.content_hash()))
+          .collect();
+
+        Ok(BulkFlush { indexed, failures })
+      });
+
+    Box::new(future)
+  }
+}
@@ -4,12 +4,222 @@ use std::collections::BTreeMap;
 use std::error::Error;
 use std::fs::File;
 use std::io::{BufReader, Read};
+use std::time::Duration;
 
 // Struct representation of the YAML configuration file
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Config {
   pub api_keys: BTreeMap<String, String>,
   pub paths: Vec<String>,
+
+  #[serde(default)]
+  pub concurrency: ConcurrencyConfig,
+
+  #[serde(default)]
+  pub search: SearchConfig,
+
+  #[serde(default)]
+  pub watch: WatchConfig,
+
+  #[serde(default)]
+  pub acoustid: AcoustIdConfig,
+
+  #[serde(default)]
+  pub metadata: MetadataConfig,
+}
+
+// Limits on how much work the scan pipeline may do at the same time
+//
+// Each limit sizes the thread pool used for that phase of processing a file,
+// so fingerprinting a handful of large FLAC files cannot starve the metadata
+// reads and database writes of other files.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct ConcurrencyConfig {
+  pub metadata: usize,
+  pub fingerprint: usize,
+  pub writes: usize,
+}
+
+impl Default for ConcurrencyConfig {
+  fn default() -> Self {
+    Self {
+      metadata: 4,
+      fingerprint: 2,
+      writes: 4,
+    }
+  }
+}
+
+impl ConcurrencyConfig {
+  // Number of files allowed to be in the pipeline at once, enough to keep
+  // every phase busy without walking ahead of the slowest one
+  pub fn files_in_flight(&self) -> usize {
+    self.metadata + self.fingerprint + self.writes
+  }
+}
+
+// Batching of documents sent to the Elasticsearch bulk API during a scan
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct SearchConfig {
+  // Number of documents sent in each bulk request
+  pub bulk_size: usize,
+
+  // Seconds a partial batch may wait before it is sent anyway
+  pub bulk_interval: u64,
+}
+
+impl Default for SearchConfig {
+  fn default() -> Self {
+    Self {
+      bulk_size: 500,
+      bulk_interval: 5,
+    }
+  }
+}
+
+impl SearchConfig {
+  pub fn bulk_interval(&self) -> Duration {
+    Duration::from_secs(self.bulk_interval)
+  }
+}
+
+// Handling of filesystem events by the `watch` command
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct WatchConfig {
+  // Seconds a file must go without further events before it is processed,
+  // so a file still being copied is only read once it is complete
+  pub delay: u64,
+}
+
+impl Default for WatchConfig {
+  fn default() -> Self {
+    Self {
+      delay: 2,
+    }
+  }
+}
+
+impl WatchConfig {
+  pub fn delay(&self) -> Duration {
+    Duration::from_secs(self.delay)
+  }
+}
+
+// Backends reading the tags and audio properties of files
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct MetadataConfig {
+  // Readers tried in order until one can open a file, any of `mediainfo`
+  // and `ffmpeg`
+  pub readers: Vec<String>,
+
+  // Reader order for files with a given extension, overriding `readers`
+  pub formats: BTreeMap<String, Vec<String>>,
+}
+
+impl Default for MetadataConfig {
+  fn default() -> Self {
+    Self {
+      readers: vec![
+        "mediainfo".to_owned(),
+        "ffmpeg".to_owned(),
+      ],
+      formats: BTreeMap::new(),
+    }
+  }
+}
+
+// Matching files to MusicBrainz recordings through AcoustID
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct AcoustIdConfig {
+  // Lowest combined score, between 0 and 1, of a recording that is stored
+  // without review
+  pub min_score: f64,
+
+  // Number of fingerprints sent in each lookup request
+  pub batch_size: usize,
+
+  // Seconds a partial batch of fingerprints may wait before it is sent anyway
+  pub batch_wait: u64,
+
+  // Milliseconds waited before retrying a batch AcoustID rejected because
+  // of rate limiting or being unavailable, doubled on each retry
+  pub retry_delay: u64,
+
+  // Base URL of the web service, for pointing lookups at a mirror or proxy
+  pub url: String,
+
+  // Whether requests may use TLS, disabled for plain HTTP stand-ins
+  pub tls: bool,
+
+  // Metadata requested along with each recording, any of `releases`,
+  // `releasegroups`, `tracks` and `compress`
+  pub meta: Vec<String>,
+
+  // Requests sent to AcoustID per second on average, and how many may be
+  // sent at once after a pause
+  pub requests_per_second: f64,
+  pub burst: u32,
+
+  // Days a lookup response is cached for, 0 to not cache responses
+  pub cache_ttl: u64,
+}
+
+impl Default for AcoustIdConfig {
+  fn default() -> Self {
+    Self {
+      min_score: 0.7,
+      batch_size: 10,
+      batch_wait: 1,
+      retry_delay: 2000,
+      url: "https://api.acoustid.org/v2".to_owned(),
+      tls: true,
+      requests_per_second: 3.0,
+      burst: 3,
+      cache_ttl: 30,
+      meta: vec![
+        "releasegroups".to_owned(),
+        "releases".to_owned(),
+        "tracks".to_owned(),
+        "compress".to_owned(),
+      ],
+    }
+  }
+}
+
+impl AcoustIdConfig {
+  pub fn batch_wait(&self) -> Duration {
+    Duration::from_secs(self.batch_wait)
+  }
+
+  pub fn retry_delay(&self) -> Duration {
+    Duration::from_millis(self.retry_delay)
+  }
+
+  pub fn lookup_url(&self) -> String {
+    format!("{}/lookup", self.url.trim_right_matches('/'))
+  }
+
+  pub fn submit_url(&self) -> String {
+    format!("{}/submit", self.url.trim_right_matches('/'))
+  }
+
+  pub fn submission_status_url(&self) -> String {
+    format!("{}/submission_status", self.url.trim_right_matches('/'))
+  }
+
+  // Value of the `meta` parameter of a lookup, recordings are always
+  // requested
+  pub fn meta_param(&self) -> String {
+    let mut meta = vec!["recordings"];
+    meta.extend(self.meta.iter().map(|s| s.as_str()));
+
+    meta.join("+")
+  }
 }
 
 impl Config {
@@ -26,10 +236,68 @@ impl Config {
       return Err(err.description().to_owned());
     }
 
-    let config = match serde_yaml::from_str(&contents) {
+    let config: Config = match serde_yaml::from_str(&contents) {
       Ok(c) => c,
       Err(err) => return Err(format!("failed to parse yaml config: {:#?}", err)),
     };
+
+    {
+      let concurrency = &config.concurrency;
+      if concurrency.metadata == 0 || concurrency.fingerprint == 0 || concurrency.writes == 0 {
+        return Err("concurrency limits must be greater than zero".to_owned());
+      }
+    }
+
+    if config.search.bulk_size == 0 || config.search.bulk_interval == 0 {
+      return Err("search bulk size and interval must be greater than zero".to_owned());
+    }
+
+    if config.watch.delay == 0 {
+      return Err("watch delay must be greater than zero".to_owned());
+    }
+
+    if config.acoustid.min_score < 0.0 || config.acoustid.min_score > 1.0 {
+      return Err("acoustid minimum score must be between 0 and 1".to_owned());
+    }
+
+    if config.acoustid.batch_size == 0 || config.acoustid.batch_wait == 0 {
+      return Err("acoustid batch size and wait must be greater than zero".to_owned());
+    }
+
+    if config.acoustid.retry_delay == 0 {
+      return Err("acoustid retry delay must be greater than zero".to_owned());
+    }
+
+    if !config.acoustid.url.starts_with("http://") && !config.acoustid.url.starts_with("https://") {
+      return Err("acoustid url must be an http or https URL".to_owned());
+    }
+
+    if !config.acoustid.tls && config.acoustid.url.starts_with("https://") {
+      return Err("acoustid url must use http when tls is disabled".to_owned());
+    }
+
+    if config.acoustid.requests_per_second <= 0.0 || config.acoustid.burst == 0 {
+      return Err("acoustid requests per second and burst must be greater than zero".to_owned());
+    }
+
+    for meta in &config.acoustid.meta {
+      match meta.as_str() {
+        "releases" | "releasegroups" | "tracks" | "compress" => {},
+        _ => return Err(format!("unknown acoustid meta: {}", meta)),
+      };
+    }
+
+    if config.metadata.readers.is_empty() {
+      return Err("at least one metadata reader must be given".to_owned());
+    }
+
+    let formats = config.metadata.formats.values();
+    for reader in config.metadata.readers.iter().chain(formats.flat_map(|readers| readers)) {
+      match reader.as_str() {
+        "mediainfo" | "ffmpeg" => {},
+        _ => return Err(format!("unknown metadata reader: {}", reader)),
+      };
+    }
     
     Ok(config)
   }
@@ -1,4 +1,6 @@
+use std::collections::HashMap;
 use std::env;
+use std::fmt;
 use std::io;
 
 use chrono::{DateTime, Utc};
@@ -9,27 +11,58 @@ use fallible_iterator::FallibleIterator;
 use futures::Future;
 use futures_cpupool::CpuPool;
 use postgres::{Connection, TlsMode};
+use postgres::types::ToSql;
 use r2d2::Pool;
 use uuid::Uuid;
 
 use diesel::prelude::*;
 
-use models::{AcoustIdLastCheck, MediaFileInfo, MusicBrainzRecording, NewMediaFileInfo};
+use models::{AcoustIdCandidate, AcoustIdLastCheck, AcoustIdReview, AcoustIdSubmissionRecord, CachedResponse, ExistingFile, Fingerprint, MediaFileInfo, MusicBrainzRecording, NewAcoustIdCandidate, NewAcoustIdSubmission, NewMediaFileInfo, NewScanFailure, ReleaseInfo, ReviewStatus, ScanFailure};
 
 fn get_database_url() -> String {
   env::var("DATABASE_URL").expect("DATABASE_URL must be set")
 }
 
+// Query errors are turned into `io::Error`s like connection pool timeouts so
+// a single failing query does not take down the whole process
+fn query_error<S: AsRef<str>, E: fmt::Display>(context: S, err: E) -> io::Error {
+  io::Error::new(io::ErrorKind::Other, format!("{}: {}", context.as_ref(), err))
+}
+
+// Store the recording an entry was matched to and the release it was matched
+// on, clearing the indexed hash so the entry is indexed again
+fn set_recording(conn: &PgConnection, db_id: i32, recording: Uuid, release: ReleaseInfo) -> QueryResult<usize> {
+  use schema::library;
+
+  diesel::update(library::table)
+    .filter(library::id.eq(db_id))
+    .set((
+      library::mbid.eq(recording),
+      library::indexed_hash.eq(None::<i64>),
+      library::release_id.eq(release.release_id),
+      library::release_group_id.eq(release.release_group_id),
+      library::medium_position.eq(release.medium_position),
+      library::track_position.eq(release.track_position),
+      library::release_date.eq(release.release_date),
+    ))
+    .execute(conn)
+}
+
 pub struct DatabaseConnection {
   pool: Pool<ConnectionManager<PgConnection>>,
   thread_pool: CpuPool,
 }
 
 impl DatabaseConnection {
-  pub fn new(thread_pool: CpuPool) -> Self {
+  // `max_size` bounds the number of connections, and therefore the number of
+  // queries that can be in flight at once
+  pub fn new(thread_pool: CpuPool, max_size: u32) -> Self {
     let database_url = get_database_url();
     let manager = ConnectionManager::<PgConnection>::new(&*database_url);
-    let pool = Pool::builder().build(manager).expect("Failed to create pool");
+    let pool = Pool::builder()
+      .max_size(max_size)
+      .build(manager)
+      .expect("Failed to create pool");
 
     Self {
       pool,
@@ -51,7 +84,7 @@ impl DatabaseConnection {
       let info = diesel::insert_into(library::table)
         .values(&info)
         .get_result(&conn)
-        .expect("Error saving new media file entry");
+        .map_err(|e| query_error("Error saving new media file entry", e))?;
 
       Ok(info)
     })
@@ -70,17 +103,60 @@ impl DatabaseConnection {
       let info = library.filter(path.eq(&file_path))
         .first::<MediaFileInfo>(&conn)
         .optional()
-        .expect("Error loading media file entry");
+        .map_err(|e| query_error("Error loading media file entry", e))?;
 
       Ok(info)
     })
   }
 
+  // Fetch the entries a new file could have been moved from
+  pub fn fetch_files_by_duration(&self, file_duration: u32, file_track_number: u32) -> impl Future<Item = Vec<MediaFileInfo>, Error = io::Error> + Send {
+    use schema::library::dsl::{library, duration, track_number};
+
+    let db = self.pool.clone();
+
+    self.thread_pool.spawn_fn(move || {
+      let conn = db.get().map_err(|e| {
+        io::Error::new(io::ErrorKind::Other, format!("timeout: {}", e))
+      })?;
+
+      let files = library.filter(duration.eq(file_duration))
+        .filter(track_number.eq(file_track_number))
+        .load::<MediaFileInfo>(&conn)
+        .map_err(|e| query_error(format!("Error loading media file entries with duration: {}", file_duration), e))?;
+
+      Ok(files)
+    })
+  }
+
+  // Fetch a page of entries ordered by id, starting after `last_id`
+  pub fn fetch_files_after(&self, last_id: i32, limit: i64) -> impl Future<Item = Vec<MediaFileInfo>, Error = io::Error> + Send {
+    use schema::library::dsl::{library, id};
+
+    let db = self.pool.clone();
+
+    self.thread_pool.spawn_fn(move || {
+      let conn = db.get().map_err(|e| {
+        io::Error::new(io::ErrorKind::Other, format!("timeout: {}", e))
+      })?;
+
+      let files = library.filter(id.gt(last_id))
+        .order(id)
+        .limit(limit)
+        .load::<MediaFileInfo>(&conn)
+        .map_err(|e| query_error(format!("Error loading media file entries after id: {}", last_id), e))?;
+
+      Ok(files)
+    })
+  }
+
+  // Every update to an entry clears `indexed_hash`, so a scan that skips
+  // unchanged files by their preloaded state still sends the new document
   pub fn update_file(&self, db_id: i32, info: NewMediaFileInfo) -> impl Future<Item = MediaFileInfo, Error = io::Error> + Send {
     let db = self.pool.clone();
 
     self.thread_pool.spawn_fn(move || {
-      use schema::library::dsl::{library, id};
+      use schema::library::dsl::{library, id, indexed_hash};
 
       let conn = db.get().map_err(|e| {
         io::Error::new(io::ErrorKind::Other, format!("timeout: {}", e))
@@ -88,14 +164,73 @@ impl DatabaseConnection {
 
       let info = diesel::update(library)
         .filter(id.eq(db_id))
-        .set(&info)
+        .set((&info, indexed_hash.eq(None::<i64>)))
         .get_result::<MediaFileInfo>(&conn)
-        .expect(&format!("Unable to find media file entry for id: {}", db_id));
+        .map_err(|e| query_error(format!("Unable to find media file entry for id: {}", db_id), e))?;
 
       Ok(info)
     })
   }
 
+  // Store the hashes of the documents successfully sent to Elasticsearch
+  pub fn update_indexed_hashes(&self, hashes: Vec<(i32, i64)>) -> impl Future<Item = (), Error = io::Error> + Send {
+    let db = self.pool.clone();
+
+    self.thread_pool.spawn_fn(move || {
+      use diesel::sql_types::{Array, Int4, Int8};
+
+      if hashes.is_empty() {
+        return Ok(());
+      }
+
+      let conn = db.get().map_err(|e| {
+        io::Error::new(io::ErrorKind::Other, format!("timeout: {}", e))
+      })?;
+
+      let (ids, hashes): (Vec<i32>, Vec<i64>) = hashes.into_iter().unzip();
+
+      diesel::sql_query(r#"
+        UPDATE library
+        SET indexed_hash = v.hash
+        FROM unnest($1, $2) AS v(id, hash)
+        WHERE library.id = v.id
+      "#)
+        .bind::<Array<Int4>, _>(ids)
+        .bind::<Array<Int8>, _>(hashes)
+        .execute(&conn)
+        .map_err(|e| query_error("Error updating indexed hashes", e))?;
+
+      Ok(())
+    })
+  }
+
+  // Point the entry for `from`, or every entry under it when it is a
+  // directory, at the same file under `to`, resolving to the number of
+  // entries changed
+  pub fn rename_path(&self, from: String, to: String) -> impl Future<Item = usize, Error = io::Error> + Send {
+    let db = self.pool.clone();
+
+    self.thread_pool.spawn_fn(move || {
+      use diesel::sql_types::Text;
+
+      let conn = db.get().map_err(|e| {
+        io::Error::new(io::ErrorKind::Other, format!("timeout: {}", e))
+      })?;
+
+      let count = diesel::sql_query(r#"
+        UPDATE library
+        SET path = $2 || substr(path, length($1) + 1), indexed_hash = NULL
+        WHERE path = $1 OR left(path, length($1) + 1) = $1 || '/'
+      "#)
+        .bind::<Text, _>(&from)
+        .bind::<Text, _>(&to)
+        .execute(&conn)
+        .map_err(|e| query_error(format!("Unable to rename {} to {}", from, to), e))?;
+
+      Ok(count)
+    })
+  }
+
   pub fn delete_file(&self, db_id: i32) -> impl Future<Item = (), Error = io::Error> + Send {
     let db = self.pool.clone();
 
@@ -109,7 +244,7 @@ impl DatabaseConnection {
       diesel::delete(library)
         .filter(id.eq(db_id))
         .execute(&conn)
-        .expect(&format!("Unable to delete media file entry for id: {}", db_id));
+        .map_err(|e| query_error(format!("Unable to delete media file entry for id: {}", db_id), e))?;
 
       Ok(())
     })
@@ -129,12 +264,31 @@ impl DatabaseConnection {
       let path_id = library.filter(path.eq(&file_path))
         .select(id)
         .first::<i32>(&conn)
-        .expect(&format!("Unable to get media file entry id for path: {}", file_path));
+        .map_err(|e| query_error(format!("Unable to get media file entry id for path: {}", file_path), e))?;
 
       Ok(path_id)
     })
   }
 
+  pub fn get_ids(&self) -> impl Future<Item = Vec<i32>, Error = io::Error> + Send {
+    let db = self.pool.clone();
+
+    self.thread_pool.spawn_fn(move || {
+      use schema::library::dsl::{library, id};
+
+      let conn = db.get().map_err(|e| {
+        io::Error::new(io::ErrorKind::Other, format!("timeout: {}", e))
+      })?;
+
+      let ids = library
+        .select(id)
+        .load::<i32>(&conn)
+        .map_err(|e| query_error("Unable to get media file entry ids", e))?;
+
+      Ok(ids)
+    })
+  }
+
   pub fn get_acoustid_last_check(&self, info: MediaFileInfo) -> impl Future<Item = Option<DateTime<Utc>>, Error = io::Error> + Send {
     let db = self.pool.clone();
 
@@ -149,7 +303,7 @@ impl DatabaseConnection {
         .select(last_check)
         .first(&conn)
         .optional()
-        .expect(&format!("Unable to get acoustid last check for info: {:?}", info));;
+        .map_err(|e| query_error(format!("Unable to get acoustid last check for info: {:?}", info), e))?;
 
       Ok(last_check_time)
     })
@@ -172,7 +326,7 @@ impl DatabaseConnection {
       "#)
         .bind::<diesel::sql_types::Uuid, _>(uuid)
         .get_results(&conn)
-        .expect("Error checking MusicBrainz UUID");
+        .map_err(|e| query_error("Error checking MusicBrainz UUID", e))?;
 
       debug!("uuid check count: {:?}", counts);
 
@@ -180,21 +334,68 @@ impl DatabaseConnection {
     })
   }
 
-  pub fn update_file_uuid(&self, db_id: i32, uuid: Uuid) -> impl Future<Item = (), Error = io::Error> + Send {
+  pub fn update_file_uuid(&self, db_id: i32, uuid: Uuid, release: ReleaseInfo) -> impl Future<Item = (), Error = io::Error> + Send {
+    let db = self.pool.clone();
+
+    self.thread_pool.spawn_fn(move || {
+      let conn = db.get().map_err(|e| {
+        io::Error::new(io::ErrorKind::Other, format!("timeout: {}", e))
+      })?;
+
+      set_recording(&conn, db_id, uuid, release)
+        .map_err(|e| query_error(format!("Error updating media file entry mbid for id: {}", db_id), e))?;
+
+      Ok(())
+    })
+  }
+
+  // Store a recording AcoustID matched the entry's fingerprint to, both as
+  // the entry's MusicBrainz ID and as its fingerprinted one
+  pub fn update_fingerprint_uuid(&self, db_id: i32, uuid: Uuid, release: ReleaseInfo) -> impl Future<Item = (), Error = io::Error> + Send {
     let db = self.pool.clone();
 
     self.thread_pool.spawn_fn(move || {
-      use schema::library::dsl::{library, id, mbid};
+      use schema::library;
 
       let conn = db.get().map_err(|e| {
         io::Error::new(io::ErrorKind::Other, format!("timeout: {}", e))
       })?;
 
-      diesel::update(library)
-        .filter(id.eq(db_id))
-        .set(mbid.eq(uuid))
+      conn.transaction::<_, diesel::result::Error, _>(|| {
+        set_recording(&conn, db_id, uuid, release)?;
+
+        diesel::update(library::table)
+          .filter(library::id.eq(db_id))
+          .set(library::fingerprint_mbid.eq(uuid))
+          .execute(&conn)?;
+
+        Ok(())
+      }).map_err(|e| query_error(format!("Error updating media file entry fingerprint mbid for id: {}", db_id), e))?;
+
+      Ok(())
+    })
+  }
+
+  // Store a recording AcoustID matched the entry's fingerprint to without
+  // making it the entry's MusicBrainz ID, for entries tagged with one
+  pub fn update_fingerprint_mbid(&self, db_id: i32, uuid: Uuid) -> impl Future<Item = (), Error = io::Error> + Send {
+    let db = self.pool.clone();
+
+    self.thread_pool.spawn_fn(move || {
+      use schema::library;
+
+      let conn = db.get().map_err(|e| {
+        io::Error::new(io::ErrorKind::Other, format!("timeout: {}", e))
+      })?;
+
+      diesel::update(library::table)
+        .filter(library::id.eq(db_id))
+        .set((
+          library::fingerprint_mbid.eq(uuid),
+          library::indexed_hash.eq(None::<i64>),
+        ))
         .execute(&conn)
-        .expect(&format!("Error updating media file entry mbid for id: {}", db_id));
+        .map_err(|e| query_error(format!("Error updating media file entry fingerprint mbid for id: {}", db_id), e))?;
 
       Ok(())
     })
@@ -216,7 +417,7 @@ impl DatabaseConnection {
           last_check.eq(current_time)
         ))
         .execute(&conn)
-        .expect(&format!("Error adding last check for library id: {}", db_library_id));
+        .map_err(|e| query_error(format!("Error adding last check for library id: {}", db_library_id), e))?;
 
       Ok(())
     });
@@ -238,7 +439,7 @@ impl DatabaseConnection {
         .filter(library_id.eq(db_library_id))
         .set(last_check.eq(current_time))
         .execute(&conn)
-        .expect(&format!("Error updating last check for library id: {}", db_library_id));
+        .map_err(|e| query_error(format!("Error updating last check for library id: {}", db_library_id), e))?;
 
       Ok(())
     });
@@ -259,12 +460,553 @@ impl DatabaseConnection {
       diesel::delete(acoustid_last_checks)
         .filter(library_id.eq(db_library_id))
         .execute(&conn)
-        .expect(&format!("Error deleting last check for library id: {}", db_library_id));
+        .map_err(|e| query_error(format!("Error deleting last check for library id: {}", db_library_id), e))?;
+
+      Ok(())
+    })
+  }
+
+  // Replace the stored AcoustID candidates of an entry with the candidates
+  // from its latest lookup
+  pub fn replace_acoustid_candidates(&self, db_library_id: i32, candidates: Vec<NewAcoustIdCandidate>) -> impl Future<Item = (), Error = io::Error> + Send {
+    let db = self.pool.clone();
+
+    self.thread_pool.spawn_fn(move || {
+      use schema::acoustid_candidates::dsl::{acoustid_candidates, library_id};
+
+      let conn = db.get().map_err(|e| {
+        io::Error::new(io::ErrorKind::Other, format!("timeout: {}", e))
+      })?;
+
+      conn.transaction::<_, diesel::result::Error, _>(|| {
+        diesel::delete(acoustid_candidates)
+          .filter(library_id.eq(db_library_id))
+          .execute(&conn)?;
+
+        if !candidates.is_empty() {
+          diesel::insert_into(acoustid_candidates)
+            .values(&candidates)
+            .execute(&conn)?;
+        }
+
+        Ok(())
+      }).map_err(|e| query_error(format!("Error saving AcoustID candidates for library id: {}", db_library_id), e))?;
+
+      Ok(())
+    })
+  }
+
+  // Stored candidates of an entry, best AcoustID score first
+  pub fn fetch_acoustid_candidates(&self, db_library_id: i32) -> impl Future<Item = Vec<AcoustIdCandidate>, Error = io::Error> + Send {
+    let db = self.pool.clone();
+
+    self.thread_pool.spawn_fn(move || {
+      use schema::acoustid_candidates::dsl::{acoustid_candidates, library_id, score};
+
+      let conn = db.get().map_err(|e| {
+        io::Error::new(io::ErrorKind::Other, format!("timeout: {}", e))
+      })?;
+
+      let candidates = acoustid_candidates
+        .filter(library_id.eq(db_library_id))
+        .order(score.desc())
+        .load::<AcoustIdCandidate>(&conn)
+        .map_err(|e| query_error(format!("Error loading AcoustID candidates for library id: {}", db_library_id), e))?;
+
+      Ok(candidates)
+    })
+  }
+
+  pub fn fetch_acoustid_review(&self, db_library_id: i32) -> impl Future<Item = Option<AcoustIdReview>, Error = io::Error> + Send {
+    let db = self.pool.clone();
+
+    self.thread_pool.spawn_fn(move || {
+      use schema::acoustid_reviews::dsl::{acoustid_reviews, library_id};
+
+      let conn = db.get().map_err(|e| {
+        io::Error::new(io::ErrorKind::Other, format!("timeout: {}", e))
+      })?;
+
+      let review = acoustid_reviews
+        .filter(library_id.eq(db_library_id))
+        .first::<AcoustIdReview>(&conn)
+        .optional()
+        .map_err(|e| query_error(format!("Error loading AcoustID review for library id: {}", db_library_id), e))?;
+
+      Ok(review)
+    })
+  }
+
+  // Queue an entry for review, keeping any existing review as it is
+  pub fn add_pending_review(&self, db_library_id: i32) -> impl Future<Item = (), Error = io::Error> + Send {
+    let db = self.pool.clone();
+
+    self.thread_pool.spawn_fn(move || {
+      use schema::acoustid_reviews::dsl::{acoustid_reviews, library_id};
+
+      let conn = db.get().map_err(|e| {
+        io::Error::new(io::ErrorKind::Other, format!("timeout: {}", e))
+      })?;
+
+      diesel::insert_into(acoustid_reviews)
+        .values(library_id.eq(db_library_id))
+        .on_conflict_do_nothing()
+        .execute(&conn)
+        .map_err(|e| query_error(format!("Error adding AcoustID review for library id: {}", db_library_id), e))?;
+
+      Ok(())
+    })
+  }
+
+  // Drop a review that has not been decided, used once a later lookup finds
+  // a clear match
+  pub fn clear_pending_review(&self, db_library_id: i32) -> impl Future<Item = (), Error = io::Error> + Send {
+    let db = self.pool.clone();
+
+    self.thread_pool.spawn_fn(move || {
+      use schema::acoustid_reviews::dsl::{acoustid_reviews, library_id, status};
+
+      let conn = db.get().map_err(|e| {
+        io::Error::new(io::ErrorKind::Other, format!("timeout: {}", e))
+      })?;
+
+      diesel::delete(acoustid_reviews)
+        .filter(library_id.eq(db_library_id))
+        .filter(status.eq(ReviewStatus::Pending.as_str()))
+        .execute(&conn)
+        .map_err(|e| query_error(format!("Error clearing AcoustID review for library id: {}", db_library_id), e))?;
+
+      Ok(())
+    })
+  }
+
+  // Entries waiting for review, ordered by path
+  pub fn fetch_pending_reviews(&self) -> impl Future<Item = Vec<MediaFileInfo>, Error = io::Error> + Send {
+    let db = self.pool.clone();
+
+    self.thread_pool.spawn_fn(move || {
+      use schema::{acoustid_reviews, library};
+
+      let conn = db.get().map_err(|e| {
+        io::Error::new(io::ErrorKind::Other, format!("timeout: {}", e))
+      })?;
+
+      let files = library::table
+        .inner_join(acoustid_reviews::table)
+        .filter(acoustid_reviews::status.eq(ReviewStatus::Pending.as_str()))
+        .select(library::all_columns)
+        .order(library::path)
+        .load::<MediaFileInfo>(&conn)
+        .map_err(|e| query_error("Error loading pending AcoustID reviews", e))?;
+
+      Ok(files)
+    })
+  }
+
+  // Record the decision of a review, storing the chosen MusicBrainz ID on the
+  // entry if there is one
+  pub fn decide_review(&self, db_library_id: i32, decision: ReviewStatus, chosen: Option<Uuid>) -> impl Future<Item = (), Error = io::Error> + Send {
+    let db = self.pool.clone();
+
+    self.thread_pool.spawn_fn(move || {
+      use schema::{acoustid_candidates, acoustid_reviews, library};
+
+      let conn = db.get().map_err(|e| {
+        io::Error::new(io::ErrorKind::Other, format!("timeout: {}", e))
+      })?;
+
+      conn.transaction::<_, diesel::result::Error, _>(|| {
+        diesel::update(acoustid_reviews::table)
+          .filter(acoustid_reviews::library_id.eq(db_library_id))
+          .set((
+            acoustid_reviews::status.eq(decision.as_str()),
+            acoustid_reviews::mbid.eq(chosen),
+            acoustid_reviews::decided_at.eq(Utc::now()),
+          ))
+          .execute(&conn)?;
+
+        if let Some(chosen) = chosen {
+          // A recording that was not among the candidates has no known
+          // release
+          let release = acoustid_candidates::table
+            .filter(acoustid_candidates::library_id.eq(db_library_id))
+            .filter(acoustid_candidates::recording_id.eq(chosen))
+            .order(acoustid_candidates::score.desc())
+            .first::<AcoustIdCandidate>(&conn)
+            .optional()?
+            .map(|candidate| candidate.release())
+            .unwrap_or_default();
+
+          set_recording(&conn, db_library_id, chosen, release)?;
+
+          // Candidates came from AcoustID, a manual choice did not
+          if decision == ReviewStatus::Accepted {
+            diesel::update(library::table)
+              .filter(library::id.eq(db_library_id))
+              .set(library::fingerprint_mbid.eq(chosen))
+              .execute(&conn)?;
+          }
+        }
+
+        Ok(())
+      }).map_err(|e| query_error(format!("Error saving AcoustID review for library id: {}", db_library_id), e))?;
+
+      Ok(())
+    })
+  }
+
+  // Entries whose tagged MusicBrainz ID differs from the one AcoustID matched
+  // their fingerprint to
+  pub fn fetch_mbid_disagreements(&self) -> impl Future<Item = Vec<MediaFileInfo>, Error = io::Error> + Send {
+    let db = self.pool.clone();
+
+    self.thread_pool.spawn_fn(move || {
+      use schema::library::dsl::{fingerprint_mbid, library, path, tagged_mbid};
+
+      let conn = db.get().map_err(|e| {
+        io::Error::new(io::ErrorKind::Other, format!("timeout: {}", e))
+      })?;
+
+      let files = library
+        .filter(tagged_mbid.is_not_null())
+        .filter(fingerprint_mbid.is_not_null())
+        .filter(tagged_mbid.ne(fingerprint_mbid))
+        .order(path)
+        .load::<MediaFileInfo>(&conn)
+        .map_err(|e| query_error("Error loading entries with disagreeing MusicBrainz IDs", e))?;
+
+      Ok(files)
+    })
+  }
+
+  // Entries that were looked up on AcoustID without any result and have not
+  // been submitted yet
+  pub fn fetch_unknown_files(&self) -> impl Future<Item = Vec<MediaFileInfo>, Error = io::Error> + Send {
+    let db = self.pool.clone();
+
+    self.thread_pool.spawn_fn(move || {
+      use diesel::dsl::not;
+      use schema::{acoustid_candidates, acoustid_last_checks, acoustid_submissions, library};
+
+      let conn = db.get().map_err(|e| {
+        io::Error::new(io::ErrorKind::Other, format!("timeout: {}", e))
+      })?;
+
+      let files = library::table
+        .filter(library::id.eq_any(acoustid_last_checks::table.select(acoustid_last_checks::library_id)))
+        .filter(not(library::id.eq_any(acoustid_candidates::table.select(acoustid_candidates::library_id))))
+        .filter(not(library::id.eq_any(acoustid_submissions::table.select(acoustid_submissions::library_id))))
+        .order(library::path)
+        .load::<MediaFileInfo>(&conn)
+        .map_err(|e| query_error("Error loading files unknown to AcoustID", e))?;
+
+      Ok(files)
+    })
+  }
+
+  pub fn add_submissions(&self, submissions: Vec<NewAcoustIdSubmission>) -> impl Future<Item = (), Error = io::Error> + Send {
+    let db = self.pool.clone();
+
+    self.thread_pool.spawn_fn(move || {
+      use schema::acoustid_submissions::dsl::acoustid_submissions;
+
+      let conn = db.get().map_err(|e| {
+        io::Error::new(io::ErrorKind::Other, format!("timeout: {}", e))
+      })?;
+
+      diesel::insert_into(acoustid_submissions)
+        .values(&submissions)
+        .on_conflict_do_nothing()
+        .execute(&conn)
+        .map_err(|e| query_error("Error saving AcoustID submissions", e))?;
+
+      Ok(())
+    })
+  }
+
+  pub fn fetch_pending_submissions(&self) -> impl Future<Item = Vec<AcoustIdSubmissionRecord>, Error = io::Error> + Send {
+    let db = self.pool.clone();
+
+    self.thread_pool.spawn_fn(move || {
+      use schema::acoustid_submissions::dsl::{acoustid_submissions, status, submission_id};
+
+      let conn = db.get().map_err(|e| {
+        io::Error::new(io::ErrorKind::Other, format!("timeout: {}", e))
+      })?;
+
+      let submissions = acoustid_submissions
+        .filter(status.eq("pending"))
+        .order(submission_id)
+        .load::<AcoustIdSubmissionRecord>(&conn)
+        .map_err(|e| query_error("Error loading pending AcoustID submissions", e))?;
+
+      Ok(submissions)
+    })
+  }
+
+  pub fn update_submission_status(&self, db_submission_id: i64, new_status: String, track_id: Option<String>) -> impl Future<Item = (), Error = io::Error> + Send {
+    let db = self.pool.clone();
+
+    self.thread_pool.spawn_fn(move || {
+      use schema::acoustid_submissions::dsl::{acoustid_submissions, acoustid_id, checked_at, status, submission_id};
+
+      let conn = db.get().map_err(|e| {
+        io::Error::new(io::ErrorKind::Other, format!("timeout: {}", e))
+      })?;
+
+      diesel::update(acoustid_submissions)
+        .filter(submission_id.eq(db_submission_id))
+        .set((status.eq(new_status), acoustid_id.eq(track_id), checked_at.eq(Utc::now())))
+        .execute(&conn)
+        .map_err(|e| query_error(format!("Error updating AcoustID submission: {}", db_submission_id), e))?;
+
+      Ok(())
+    })
+  }
+
+  pub fn fetch_fingerprint(&self, db_library_id: i32) -> impl Future<Item = Option<Fingerprint>, Error = io::Error> + Send {
+    let db = self.pool.clone();
+
+    self.thread_pool.spawn_fn(move || {
+      use schema::fingerprints::dsl::{fingerprints, library_id};
+
+      let conn = db.get().map_err(|e| {
+        io::Error::new(io::ErrorKind::Other, format!("timeout: {}", e))
+      })?;
+
+      let fingerprint = fingerprints
+        .filter(library_id.eq(db_library_id))
+        .first::<Fingerprint>(&conn)
+        .optional()
+        .map_err(|e| query_error(format!("Error loading fingerprint for library id: {}", db_library_id), e))?;
+
+      Ok(fingerprint)
+    })
+  }
+
+  pub fn fetch_fingerprints(&self) -> impl Future<Item = Vec<Fingerprint>, Error = io::Error> + Send {
+    let db = self.pool.clone();
+
+    self.thread_pool.spawn_fn(move || {
+      use schema::fingerprints::dsl::fingerprints;
+
+      let conn = db.get().map_err(|e| {
+        io::Error::new(io::ErrorKind::Other, format!("timeout: {}", e))
+      })?;
+
+      let rows = fingerprints
+        .load::<Fingerprint>(&conn)
+        .map_err(|e| query_error("Error loading fingerprints", e))?;
+
+      Ok(rows)
+    })
+  }
+
+  // Store the fingerprint of an entry's file, replacing the previous one
+  pub fn save_fingerprint(&self, fingerprint: Fingerprint) -> impl Future<Item = (), Error = io::Error> + Send {
+    let db = self.pool.clone();
+
+    self.thread_pool.spawn_fn(move || {
+      use schema::fingerprints::dsl::{fingerprints, library_id};
+
+      let conn = db.get().map_err(|e| {
+        io::Error::new(io::ErrorKind::Other, format!("timeout: {}", e))
+      })?;
+
+      diesel::insert_into(fingerprints)
+        .values(&fingerprint)
+        .on_conflict(library_id)
+        .do_update()
+        .set(&fingerprint)
+        .execute(&conn)
+        .map_err(|e| query_error(format!("Error saving fingerprint for library id: {}", fingerprint.library_id), e))?;
+
+      Ok(())
+    })
+  }
+
+  // Cached AcoustID results for a request key, if they were fetched after
+  // `fresh_after`
+  pub fn fetch_cached_response(&self, response_key: i64, fresh_after: DateTime<Utc>) -> impl Future<Item = Option<CachedResponse>, Error = io::Error> + Send {
+    let db = self.pool.clone();
+
+    self.thread_pool.spawn_fn(move || {
+      use schema::acoustid_responses::dsl::{acoustid_responses, fetched_at, key};
+
+      let conn = db.get().map_err(|e| {
+        io::Error::new(io::ErrorKind::Other, format!("timeout: {}", e))
+      })?;
+
+      let response = acoustid_responses
+        .filter(key.eq(response_key))
+        .filter(fetched_at.gt(fresh_after))
+        .first::<CachedResponse>(&conn)
+        .optional()
+        .map_err(|e| query_error(format!("Error loading cached AcoustID response for key: {}", response_key), e))?;
+
+      Ok(response)
+    })
+  }
+
+  pub fn save_cached_response(&self, response: CachedResponse) -> impl Future<Item = (), Error = io::Error> + Send {
+    let db = self.pool.clone();
+
+    self.thread_pool.spawn_fn(move || {
+      use schema::acoustid_responses::dsl::{acoustid_responses, key};
+
+      let conn = db.get().map_err(|e| {
+        io::Error::new(io::ErrorKind::Other, format!("timeout: {}", e))
+      })?;
+
+      diesel::insert_into(acoustid_responses)
+        .values(&response)
+        .on_conflict(key)
+        .do_update()
+        .set(&response)
+        .execute(&conn)
+        .map_err(|e| query_error(format!("Error saving cached AcoustID response for key: {}", response.key), e))?;
+
+      Ok(())
+    })
+  }
+
+  // Delete cached AcoustID responses fetched before `before`, resolving to
+  // the number deleted
+  pub fn purge_cached_responses(&self, before: DateTime<Utc>) -> impl Future<Item = usize, Error = io::Error> + Send {
+    let db = self.pool.clone();
+
+    self.thread_pool.spawn_fn(move || {
+      use schema::acoustid_responses::dsl::{acoustid_responses, fetched_at};
+
+      let conn = db.get().map_err(|e| {
+        io::Error::new(io::ErrorKind::Other, format!("timeout: {}", e))
+      })?;
+
+      let count = diesel::delete(acoustid_responses.filter(fetched_at.lt(before)))
+        .execute(&conn)
+        .map_err(|e| query_error("Error purging cached AcoustID responses", e))?;
+
+      Ok(count)
+    })
+  }
+
+  pub fn add_scan_failure(&self, failure: NewScanFailure) -> impl Future<Item = (), Error = io::Error> + Send {
+    let db = self.pool.clone();
+
+    self.thread_pool.spawn_fn(move || {
+      use schema::scan_failures::dsl::{scan_failures, path};
+
+      let conn = db.get().map_err(|e| {
+        io::Error::new(io::ErrorKind::Other, format!("timeout: {}", e))
+      })?;
+
+      // Only the most recent failure for a path is kept
+      diesel::insert_into(scan_failures)
+        .values(&failure)
+        .on_conflict(path)
+        .do_update()
+        .set(&failure)
+        .execute(&conn)
+        .map_err(|e| query_error(format!("Error adding scan failure for path: {}", failure.path), e))?;
 
       Ok(())
     })
   }
 
+  pub fn delete_scan_failure(&self, file_path: String) -> impl Future<Item = (), Error = io::Error> + Send {
+    let db = self.pool.clone();
+
+    self.thread_pool.spawn_fn(move || {
+      use schema::scan_failures::dsl::{scan_failures, path};
+
+      let conn = db.get().map_err(|e| {
+        io::Error::new(io::ErrorKind::Other, format!("timeout: {}", e))
+      })?;
+
+      diesel::delete(scan_failures)
+        .filter(path.eq(&file_path))
+        .execute(&conn)
+        .map_err(|e| query_error(format!("Error deleting scan failure for path: {}", file_path), e))?;
+
+      Ok(())
+    })
+  }
+
+  pub fn fetch_scan_failures(&self) -> impl Future<Item = Vec<ScanFailure>, Error = io::Error> + Send {
+    let db = self.pool.clone();
+
+    self.thread_pool.spawn_fn(move || {
+      use schema::scan_failures::dsl::{scan_failures, path};
+
+      let conn = db.get().map_err(|e| {
+        io::Error::new(io::ErrorKind::Other, format!("timeout: {}", e))
+      })?;
+
+      let failures = scan_failures
+        .order(path)
+        .load::<ScanFailure>(&conn)
+        .map_err(|e| query_error("Error loading scan failures", e))?;
+
+      Ok(failures)
+    })
+  }
+
+  // Load the state of every entry under `root` with a single streaming query,
+  // keyed by path
+  pub fn load_root_state(&self, root: &str) -> Result<HashMap<String, ExistingFile>, io::Error> {
+    // Match whole directory names so `/music` does not include `/music2`
+    let mut prefix = root.to_owned();
+    if !prefix.ends_with('/') {
+      prefix.push('/');
+    }
+
+    Self::load_states("left(library.path, length($1)) = $1", &prefix)
+  }
+
+  fn load_states(condition: &str, param: &ToSql) -> Result<HashMap<String, ExistingFile>, io::Error> {
+    let database_url = get_database_url();
+    let conn = try!(Connection::connect(&*database_url, TlsMode::None));
+    let query = format!(r#"
+      SELECT
+        library.id,
+        library.path,
+        library.mtime,
+        library.mbid,
+        library.tagged_mbid,
+        library.indexed_hash,
+        acoustid_last_checks.last_check,
+        library.metadata_version
+      FROM library
+      LEFT JOIN acoustid_last_checks ON acoustid_last_checks.library_id = library.id
+      WHERE {}
+    "#, condition);
+    let stmt = match conn.prepare(&query) {
+      Ok(v) => v,
+      Err(err) => return Err(io::Error::new(io::ErrorKind::Other, format!("error preparing load_states statement: {:#?}", err))),
+    };
+
+    let trans = try!(conn.transaction());
+    let mut rows = try!(stmt.lazy_query(&trans, &[param], 1000));
+    let mut states = HashMap::new();
+
+    while let Some(row) = rows.next()? {
+      let path: String = row.get(1);
+      let state = ExistingFile {
+        id:               row.get(0),
+        mtime:            row.get(2),
+        mbid:             row.get(3),
+        tagged_mbid:      row.get(4),
+        indexed_hash:     row.get(5),
+        last_check:       row.get(6),
+        metadata_version: row.get(7),
+      };
+
+      states.insert(path, state);
+    }
+
+    Ok(states)
+  }
+
   pub fn path_iter<F: 'static>(&self, cb: F) -> Result<(), io::Error>
     where F: Fn(i32, String) -> ()
   {
@@ -0,0 +1,354 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use futures::{Future, Stream};
+use futures::stream;
+use futures_cpupool::Builder as CpuPoolBuilder;
+use mediainfo::MediaInfo;
+use tokio_core::reactor::Core;
+
+use config::Config;
+use database::DatabaseConnection;
+use fingerprint;
+use models::{Fingerprint, MediaFileInfo};
+
+use basic_types::*;
+
+// Each item of a raw fingerprint covers about 0.124 seconds of audio, so
+// fingerprints are compared shifted by up to ~10 seconds either way to line
+// up copies with different amounts of leading silence
+static MAX_OFFSET: isize = 80;
+
+// Fewest overlapping items an alignment needs to count
+static MIN_OVERLAP: usize = 40;
+
+// Only files whose durations are this many seconds apart or closer are
+// compared at all
+static MAX_DURATION_DIFFERENCE: f64 = 10.0;
+
+// Number of library entries read from the database at a time
+static PAGE_SIZE: i64 = 500;
+
+// The fingerprint of a library entry in the form needed for comparing
+pub struct FingerprintEntry {
+  pub id: i32,
+  pub duration: f64,
+  pub algorithm: i32,
+  pub raw: Vec<u32>,
+}
+
+impl<'a> From<&'a Fingerprint> for FingerprintEntry {
+  fn from(fingerprint: &'a Fingerprint) -> Self {
+    FingerprintEntry {
+      id:        fingerprint.library_id,
+      duration:  fingerprint.duration,
+      algorithm: fingerprint.algorithm,
+      raw:       fingerprint.raw_values(),
+    }
+  }
+}
+
+// A file in a cluster of duplicates
+#[derive(Debug)]
+pub struct DuplicateFile {
+  pub id: i32,
+  pub path: String,
+  pub format: Option<String>,
+  pub bitrate: Option<String>,
+}
+
+// Lowest fraction of differing bits between two raw fingerprints over every
+// alignment, `None` if no alignment overlaps enough to compare
+pub fn bit_error_rate(a: &[u32], b: &[u32]) -> Option<f64> {
+  let mut best: Option<f64> = None;
+
+  for offset in -MAX_OFFSET..(MAX_OFFSET + 1) {
+    let (a, b) = if offset >= 0 {
+      (a.get(offset as usize..).unwrap_or(&[]), b)
+    } else {
+      (a, b.get((-offset) as usize..).unwrap_or(&[]))
+    };
+
+    let overlap = cmp::min(a.len(), b.len());
+    if overlap < MIN_OVERLAP {
+      continue;
+    }
+
+    let errors: u32 = a.iter()
+      .zip(b)
+      .map(|(x, y)| (x ^ y).count_ones())
+      .sum();
+    let rate = f64::from(errors) / (overlap * 32) as f64;
+
+    best = Some(best.map_or(rate, |best| best.min(rate)));
+  }
+
+  best
+}
+
+fn find_root(parents: &mut Vec<usize>, i: usize) -> usize {
+  let mut root = i;
+  while parents[root] != root {
+    root = parents[root];
+  }
+
+  // Point everything on the way straight at the root
+  let mut i = i;
+  while parents[i] != root {
+    let next = parents[i];
+    parents[i] = root;
+    i = next;
+  }
+
+  root
+}
+
+// Group entries whose fingerprints differ by at most `threshold` of their
+// bits, returning the ids of every group with more than one entry
+//
+// Matches are transitive, a file matching either of two copies ends up in
+// the same group as both.
+pub fn find_clusters(entries: &[FingerprintEntry], threshold: f64) -> Vec<Vec<i32>> {
+  let mut order: Vec<usize> = (0..entries.len()).collect();
+  order.sort_by(|&a, &b| entries[a].duration.partial_cmp(&entries[b].duration).unwrap_or(cmp::Ordering::Equal));
+
+  let mut parents: Vec<usize> = (0..entries.len()).collect();
+
+  for (n, &i) in order.iter().enumerate() {
+    for &j in &order[n + 1..] {
+      let (a, b) = (&entries[i], &entries[j]);
+      if b.duration - a.duration > MAX_DURATION_DIFFERENCE {
+        break;
+      }
+
+      if a.algorithm != b.algorithm || find_root(&mut parents, i) == find_root(&mut parents, j) {
+        continue;
+      }
+
+      match bit_error_rate(&a.raw, &b.raw) {
+        Some(rate) if rate <= threshold => {
+          debug!("id: {} and id: {} match, bit error rate: {:.3}", a.id, b.id, rate);
+
+          let root = find_root(&mut parents, i);
+          let other = find_root(&mut parents, j);
+          parents[other] = root;
+        },
+        _ => {},
+      };
+    }
+  }
+
+  let mut groups: HashMap<usize, Vec<i32>> = HashMap::new();
+  for (i, entry) in entries.iter().enumerate() {
+    let root = find_root(&mut parents, i);
+    groups.entry(root).or_insert_with(Vec::new).push(entry.id);
+  }
+
+  let mut clusters: Vec<Vec<i32>> = groups.into_iter()
+    .map(|(_, mut ids)| {
+      ids.sort();
+      ids
+    })
+    .filter(|ids| ids.len() > 1)
+    .collect();
+  clusters.sort();
+
+  clusters
+}
+
+// Container format and overall bitrate of a file, to help pick which copy
+// to keep
+fn describe_file(path: &str) -> (Option<String>, Option<String>) {
+  let mut media_info: MediaInfo = MediaInfo::new();
+  if media_info.open(path).is_err() {
+    return (None, None);
+  }
+
+  let format = media_info.get_with_default_options("Format").ok();
+  let bitrate = media_info.get_with_default_options("OverallBitRate").ok()
+    .and_then(|bitrate| bitrate.parse::<f64>().ok())
+    .map(|bitrate| format!("{:.0} kb/s", bitrate / 1000.0));
+
+  media_info.close();
+
+  (format, bitrate)
+}
+
+// Find copies of the same recording anywhere in the library by comparing
+// fingerprints locally, without AcoustID or Elasticsearch
+//
+// Entries that have not been fingerprinted yet, or whose file changed since,
+// are fingerprinted first and the fingerprints stored.
+pub fn find_duplicates(config: &Config, threshold: f64) -> Result<Vec<Vec<DuplicateFile>>, ProcessorError> {
+  let concurrency = &config.concurrency;
+
+  let mut core = try!(Core::new());
+  let fingerprint_pool = CpuPoolBuilder::new()
+    .pool_size(concurrency.fingerprint)
+    .name_prefix("fingerprint_thread")
+    .create();
+  let database_pool = CpuPoolBuilder::new()
+    .pool_size(concurrency.writes)
+    .name_prefix("database_thread")
+    .create();
+
+  let conn = Arc::new(DatabaseConnection::new(database_pool, concurrency.writes as u32));
+
+  let mut stored: HashMap<i32, Fingerprint> = try!(core.run(conn.fetch_fingerprints()))
+    .into_iter()
+    .map(|fingerprint| (fingerprint.library_id, fingerprint))
+    .collect();
+
+  let mut files: HashMap<i32, MediaFileInfo> = HashMap::new();
+  let mut last_id = 0;
+  loop {
+    let page = try!(core.run(conn.fetch_files_after(last_id, PAGE_SIZE)));
+    let done = (page.len() as i64) < PAGE_SIZE;
+
+    for info in page {
+      last_id = info.id;
+      files.insert(info.id, info);
+    }
+
+    if done {
+      break;
+    }
+  }
+
+  let outdated: Vec<(i32, String, _)> = files.values()
+    .filter(|info| stored.get(&info.id).map(|fingerprint| fingerprint.mtime != info.mtime).unwrap_or(true))
+    .map(|info| (info.id, info.path.clone(), info.mtime))
+    .collect();
+
+  if !outdated.is_empty() {
+    println!("Fingerprinting {} files", outdated.len());
+  }
+
+  let conn2 = Arc::clone(&conn);
+  let fingerprints = stream::iter_ok::<_, ProcessorError>(outdated).map(move |(id, path, mtime)| {
+    let conn = Arc::clone(&conn2);
+    let path2 = path.clone();
+
+    fingerprint_pool.spawn_fn(move || fingerprint::get(&path))
+      .and_then(move |(duration, compressed)| {
+        let fingerprint = try!(Fingerprint::new(id, mtime, duration, compressed)
+          .ok_or(ProcessorError::Chromaprint("unable to decode fingerprint")));
+
+        Ok(fingerprint)
+      })
+      .and_then(move |fingerprint| {
+        conn.save_fingerprint(fingerprint.clone())
+          .map_err(ProcessorError::from)
+          .map(move |_| Some(fingerprint))
+      })
+      .or_else(move |e| {
+        error!("path: {}, unable to fingerprint: {}", path2, e);
+        Ok(None)
+      })
+  })
+  .buffer_unordered(concurrency.fingerprint)
+  .filter_map(|fingerprint| fingerprint)
+  .collect();
+
+  for fingerprint in try!(core.run(fingerprints)) {
+    stored.insert(fingerprint.library_id, fingerprint);
+  }
+
+  // Fingerprints of entries that are gone are left for `prune`
+  let entries: Vec<FingerprintEntry> = stored.values()
+    .filter(|fingerprint| files.contains_key(&fingerprint.library_id))
+    .map(FingerprintEntry::from)
+    .collect();
+  drop(stored);
+
+  println!("Comparing {} fingerprints", entries.len());
+
+  let clusters = find_clusters(&entries, threshold)
+    .into_iter()
+    .map(|ids| {
+      ids.into_iter()
+        .filter_map(|id| files.remove(&id))
+        .map(|info| {
+          let (format, bitrate) = describe_file(&info.path);
+
+          DuplicateFile {
+            id: info.id,
+            path: info.path,
+            format,
+            bitrate,
+          }
+        })
+        .collect()
+    })
+    .collect();
+
+  Ok(clusters)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Deterministic stand-in for a fingerprint of unrelated audio
+  fn noise(seed: u32, len: usize) -> Vec<u32> {
+    let mut state = seed;
+
+    (0..len)
+      .map(|_| {
+        state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+        state
+      })
+      .collect()
+  }
+
+  fn entry(id: i32, duration: f64, raw: Vec<u32>) -> FingerprintEntry {
+    FingerprintEntry {
+      id,
+      duration,
+      algorithm: 1,
+      raw,
+    }
+  }
+
+  #[test]
+  fn test_bit_error_rate_offset() {
+    let a = noise(1, 200);
+    let b = a[12..].to_vec();
+
+    assert_eq!(bit_error_rate(&a, &b), Some(0.0));
+    assert_eq!(bit_error_rate(&b, &a), Some(0.0));
+  }
+
+  #[test]
+  fn test_bit_error_rate_unrelated() {
+    let rate = bit_error_rate(&noise(1, 200), &noise(2, 200)).unwrap();
+    assert!(rate > 0.3, "rate: {}", rate);
+  }
+
+  #[test]
+  fn test_bit_error_rate_too_short() {
+    assert_eq!(bit_error_rate(&noise(1, 10), &noise(1, 10)), None);
+  }
+
+  #[test]
+  fn test_find_clusters() {
+    let original = noise(1, 200);
+    let mut reencoded = original[5..].to_vec();
+    for (i, value) in reencoded.iter_mut().enumerate() {
+      if i % 4 == 0 {
+        *value ^= 0x0000_0f0f;
+      }
+    }
+
+    let entries = vec![
+      entry(1, 180.0, original.clone()),
+      entry(2, 180.5, noise(2, 200)),
+      entry(3, 181.0, reencoded),
+      // Same audio but too far apart in duration to be compared
+      entry(4, 240.0, original),
+    ];
+
+    assert_eq!(find_clusters(&entries, 0.2), vec![vec![1, 3]]);
+  }
+}
@@ -1,20 +1,34 @@
 use std::env;
 
 use elastic::client::{AsyncClientBuilder, AsyncClient};
-use elastic::client::requests::IndicesExistsRequest;
-use elastic::client::responses::{AsyncResponseBuilder, CommandResponse, IndexResponse};
+use elastic::client::requests::{
+  BulkRequest,
+  IndicesDeleteRequest,
+  IndicesExistsRequest,
+  IndicesGetAliasRequest,
+  IndicesUpdateAliasesRequest,
+  SearchRequest,
+};
+use elastic::client::responses::{AsyncResponseBuilder, CommandResponse};
 use elastic::prelude::DocumentType;
 use elastic::Error as ElasticError;
+use elastic::error::ApiError;
 use futures::Future;
-use futures::future;
+use futures::future::{self, Loop};
 use futures_cpupool::CpuPool;
-use serde_json::Value;
+use serde_json::{self, Value};
 use tokio_core::reactor::Handle;
 
 use models::MediaFileInfoDocument;
 
+// Name of the alias searches and document writes go through. The alias points
+// at a versioned index (`music_card_catalog_v1`, `music_card_catalog_v2`, ...)
+// so the index can be rebuilt and swapped in without interrupting searches.
 static INDEX_NAME: &'static str = "music_card_catalog";
 
+// Number of document ids requested per search when listing the index
+static ID_PAGE_SIZE: usize = 1000;
+
 pub struct ElasticSearch {
   client: AsyncClient,
 }
@@ -43,8 +57,13 @@ impl ElasticSearch {
     fn create_index(client: &AsyncClient) -> impl Future<Item = (), Error = ElasticError> {
       info!("Elasticsearch index does not exist, creating index");
 
-      client.index_create(INDEX_NAME.into())
-        .body(ElasticSearch::body())
+      let mut body = ElasticSearch::body();
+      body["aliases"] = json!({
+        INDEX_NAME: {},
+      });
+
+      client.index_create(ElasticSearch::versioned_index_name(1).into())
+        .body(body)
         .send()
         .and_then(|res| {
           info!("Index created with response: {:?}", res);
@@ -94,9 +113,292 @@ impl ElasticSearch {
     data
   }
 
-  pub fn insert_document(&self, doc: MediaFileInfoDocument) -> impl Future<Item = IndexResponse, Error = ElasticError> {
+  // Delete a document, counting a 404 as deleted since the document is gone
+  // either way. A missing document is already answered as ok, a missing
+  // index is not.
+  pub fn delete_document(&self, id: i32) -> impl Future<Item = (), Error = ElasticError> {
+    self.client
+      .document_delete::<MediaFileInfoDocument>(INDEX_NAME.into(), id.into())
+      .send()
+      .then(|res| match res {
+        Ok(res) => {
+          trace!("elastic delete res: {:?}", res);
+          Ok(())
+        },
+        Err(ElasticError::Api(ApiError::IndexNotFound { .. })) => Ok(()),
+        Err(err) => Err(err),
+      })
+  }
+
+  // Get the ids of every document in the index
+  //
+  // Pages through the index with `search_after` on the `id` field, which
+  // avoids having to keep a scroll context open on the server.
+  pub fn document_ids(&self) -> impl Future<Item = Vec<i32>, Error = ElasticError> {
+    let client = self.client.clone();
+
+    future::loop_fn((None, Vec::new()), move |(search_after, mut ids): (Option<i32>, Vec<i32>)| {
+      let mut body = json!({
+        "size": ID_PAGE_SIZE,
+        "_source": false,
+        "sort": [
+          { "id": "asc" },
+        ],
+      });
+
+      if let Some(last_id) = search_after {
+        body["search_after"] = json!([last_id]);
+      }
+
+      client.request(SearchRequest::for_index(INDEX_NAME, body.to_string()))
+        .send()
+        .and_then(|res| res.into_response::<Value>())
+        .map(move |res| {
+          let page: Vec<i32> = res["hits"]["hits"].as_array()
+            .map(|hits| {
+              hits.iter()
+                .filter_map(|hit| hit["_id"].as_str().and_then(|id| id.parse().ok()))
+                .collect()
+            })
+            .unwrap_or_else(Vec::new);
+
+          match page.last().cloned() {
+            Some(last_id) => {
+              ids.extend(page);
+              Loop::Continue((Some(last_id), ids))
+            },
+            None => Loop::Break(ids),
+          }
+        })
+    })
+  }
+
+  fn versioned_index_name(version: u32) -> String {
+    format!("{}_v{}", INDEX_NAME, version)
+  }
+
+  fn parse_index_version(index: &str) -> Option<u32> {
+    let prefix = format!("{}_v", INDEX_NAME);
+
+    if index.starts_with(&prefix) {
+      index[prefix.len()..].parse().ok()
+    } else {
+      None
+    }
+  }
+
+  // Get every versioned index along with whether the alias points to it
+  fn versioned_indices(client: &AsyncClient) -> impl Future<Item = Vec<(String, bool)>, Error = ElasticError> {
+    let pattern = format!("{}_v*", INDEX_NAME);
+
+    client.request(IndicesGetAliasRequest::for_index(pattern))
+      .send()
+      .and_then(|res| res.into_response::<Value>())
+      .map(|res| {
+        let indices = match res.as_object() {
+          Some(v) => v,
+          None => return Vec::new(),
+        };
+
+        indices.iter()
+          .filter(|&(name, _)| ElasticSearch::parse_index_version(name).is_some())
+          .map(|(name, value)| {
+            let aliased = value["aliases"].get(INDEX_NAME).is_some();
+
+            (name.clone(), aliased)
+          })
+          .collect()
+      })
+  }
+
+  // Create a new, empty versioned index with the current mapping and return
+  // its name. The alias is not changed.
+  pub fn create_next_index(&self) -> impl Future<Item = String, Error = ElasticError> {
+    let client = self.client.clone();
+
+    Self::versioned_indices(&self.client)
+      .and_then(move |indices| {
+        let version = indices.iter()
+          .filter_map(|&(ref name, _)| Self::parse_index_version(name))
+          .max()
+          .unwrap_or(0) + 1;
+        let index = Self::versioned_index_name(version);
+
+        client.index_create(index.clone().into())
+          .body(ElasticSearch::body())
+          .send()
+          .map(move |res| {
+            info!("Index {} created with response: {:?}", index, res);
+            index
+          })
+      })
+  }
+
+  // Atomically point the alias at `index`, returning the names of the indices
+  // it was moved away from
+  //
+  // An index created before the alias existed has the same name as the alias,
+  // that index is removed in the same request.
+  pub fn swap_alias(&self, index: String) -> impl Future<Item = Vec<String>, Error = ElasticError> {
+    let client = self.client.clone();
+
+    let legacy = self.client
+      .request(IndicesExistsRequest::for_index(INDEX_NAME))
+      .send()
+      .map(|exists| exists.status() == 200);
+
+    Self::versioned_indices(&self.client)
+      .join(legacy)
+      .and_then(move |(indices, exists)| {
+        let old: Vec<String> = indices.iter()
+          .filter(|&&(ref name, aliased)| aliased && *name != index)
+          .map(|&(ref name, _)| name.clone())
+          .collect();
+
+        let mut actions = vec![
+          json!({ "add": { "index": index, "alias": INDEX_NAME } }),
+        ];
+
+        for name in &old {
+          actions.push(json!({ "remove": { "index": name, "alias": INDEX_NAME } }));
+        }
+
+        // The alias name only resolves to a concrete index when no versioned
+        // index holds the alias
+        if exists && !indices.iter().any(|&(_, aliased)| aliased) {
+          info!("removing unversioned index {}", INDEX_NAME);
+          actions.push(json!({ "remove_index": { "index": INDEX_NAME } }));
+        }
+
+        let body = json!({ "actions": actions });
+
+        client.request(IndicesUpdateAliasesRequest::new(body.to_string()))
+          .send()
+          .and_then(|res| res.into_response::<CommandResponse>())
+          .map(move |res| {
+            info!("alias {} now points to {}: {:?}", INDEX_NAME, index, res);
+            old
+          })
+      })
+  }
+
+  pub fn delete_index(&self, index: &str) -> impl Future<Item = CommandResponse, Error = ElasticError> {
     self.client
-      .document_index(INDEX_NAME.into(), doc.id.into(), doc)
+      .request(IndicesDeleteRequest::for_index(index.to_owned()))
       .send()
+      .and_then(|res| res.into_response::<CommandResponse>())
+  }
+
+  // Index many documents into the alias with a single request to the bulk API
+  pub fn bulk_insert_documents(&self, docs: &[MediaFileInfoDocument]) -> impl Future<Item = Value, Error = ElasticError> {
+    self.bulk_index(INDEX_NAME, docs)
+  }
+
+  // Index many documents into `index` with a single request to the bulk API
+  pub fn bulk_index(&self, index: &str, docs: &[MediaFileInfoDocument]) -> impl Future<Item = Value, Error = ElasticError> {
+    let doc_type = MediaFileInfoDocument::name();
+    let mut body = String::new();
+
+    for doc in docs {
+      let action = json!({
+        "index": {
+          "_index": index,
+          "_type": doc_type,
+          "_id": doc.id,
+        },
+      });
+
+      body.push_str(&action.to_string());
+      body.push('\n');
+      body.push_str(&serde_json::to_string(doc).expect("failed to serialize document"));
+      body.push('\n');
+    }
+
+    self.client
+      .request(BulkRequest::new(body))
+      .send()
+      .and_then(|res| res.into_response::<Value>())
+  }
+
+  // Collect the document id and error message of every failed item in a bulk
+  // API response
+  pub fn bulk_item_errors(res: &Value) -> Vec<(i32, String)> {
+    if !res["errors"].as_bool().unwrap_or(false) {
+      return Vec::new();
+    }
+
+    let items = match res["items"].as_array() {
+      Some(v) => v,
+      None => return Vec::new(),
+    };
+
+    items.iter()
+      .filter_map(|item| {
+        // Each item is keyed by the action, which is always `index` here
+        let item = item.as_object().and_then(|item| item.values().next())?;
+        let error = item.get("error")?;
+
+        let id = match item["_id"] {
+          Value::String(ref id) => id.parse().ok()?,
+          ref id => id.as_i64()? as i32,
+        };
+        let message = match error["reason"].as_str() {
+          Some(reason) => format!("{}: {}", error["type"].as_str().unwrap_or("error"), reason),
+          None => error.to_string(),
+        };
+
+        Some((id, message))
+      })
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_bulk_item_errors() {
+    let json = r#"{
+      "took": 3,
+      "errors": true,
+      "items": [
+        {
+          "index": {
+            "_index": "music_card_catalog_v1",
+            "_type": "mediafileinfodocument",
+            "_id": "12",
+            "status": 201
+          }
+        },
+        {
+          "index": {
+            "_index": "music_card_catalog_v1",
+            "_type": "mediafileinfodocument",
+            "_id": "13",
+            "status": 400,
+            "error": {
+              "type": "mapper_parsing_exception",
+              "reason": "failed to parse [duration]"
+            }
+          }
+        }
+      ]
+    }"#;
+
+    let res: Value = serde_json::from_str(json).unwrap();
+    let errors = ElasticSearch::bulk_item_errors(&res);
+    assert_eq!(errors, vec![(13, "mapper_parsing_exception: failed to parse [duration]".to_owned())]);
+  }
+
+  #[test]
+  fn test_bulk_item_errors_none() {
+    let res = json!({
+      "took": 3,
+      "errors": false,
+      "items": [],
+    });
+
+    assert!(ElasticSearch::bulk_item_errors(&res).is_empty());
   }
 }
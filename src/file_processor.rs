@@ -1,3 +1,6 @@
+use std::cmp::Ordering;
+use std::io;
+use std::rc::Rc;
 use std::sync::Arc;
 
 use futures::Future;
@@ -5,48 +8,116 @@ use futures::future;
 use futures_cpupool::CpuPool;
 
 use chrono::prelude::*;
+use uuid::Uuid;
 
 use acoustid::AcoustId;
 use database::DatabaseConnection;
-use models::{MediaFileInfo, NewMediaFileInfo};
+use matcher::{self, MatchDecision};
+use metadata::{MetadataReaders, METADATA_VERSION};
+use models::{ExistingFile, MediaFileInfo, NewAcoustIdCandidate, NewMediaFileInfo, ReleaseInfo};
+use move_detector::MoveDetector;
 
 use basic_types::*;
 
 macro_rules! wrap_err {
-  ($x:expr) => {
-    $x.map_err(ProcessorError::from)
+  ($x:expr, $phase:expr) => {
+    $x.map_err(|e| ProcessorError::from(e).in_phase($phase))
   }
 }
 
+// Whether a file without a MusicBrainz ID is due to be looked up on AcoustID
+// again
+fn acoustid_check_due(last_check: Option<DateTime<Utc>>) -> bool {
+  let difference = Utc::now().timestamp() - last_check.unwrap_or_else(|| Utc.timestamp(0, 0)).timestamp();
+
+  // 2 weeks = 1,209,600 seconds
+  difference >= 1_209_600
+}
+
+// Whether an entry is due to be looked up on AcoustID. Files tagged with a
+// recording ID are looked up once, to check the tag against their
+// fingerprint.
+fn acoustid_lookup_due(mbid: Option<Uuid>, tagged_mbid: Option<Uuid>, last_check: Option<DateTime<Utc>>) -> bool {
+  if tagged_mbid.is_some() {
+    last_check.is_none()
+  } else {
+    mbid.is_none() && acoustid_check_due(last_check)
+  }
+}
+
+// Candidates of an AcoustID lookup for an entry, the decision on them and
+// the release of the best scoring candidate of the accepted recording
+fn decide_match(info: &MediaFileInfo, results: &[AcoustIdResult], min_score: f64) -> (Vec<NewAcoustIdCandidate>, MatchDecision, ReleaseInfo) {
+  let candidates = NewAcoustIdCandidate::from_results(info, results);
+  let ranked = matcher::rank(info, &candidates);
+  debug!("id: {}, ranked candidates: {:?}", info.id, ranked);
+
+  let decision = matcher::decide(&ranked, min_score);
+
+  let release = match decision {
+    MatchDecision::Accept(mbid) => candidates.iter()
+      .filter(|candidate| candidate.recording_id == mbid)
+      .max_by(|a, b| a.score.partial_cmp(&b.score).unwrap_or(Ordering::Equal))
+      .map(|candidate| candidate.release())
+      .unwrap_or_default(),
+    _ => ReleaseInfo::default(),
+  };
+
+  (candidates, decision, release)
+}
+
+fn get_mtime(path: &str) -> Result<DateTime<Utc>, ProcessorError> {
+  NewMediaFileInfo::get_mtime(path).ok_or_else(|| {
+    let err = io::Error::new(io::ErrorKind::NotFound, format!("unable to get modification time for path: {}", path));
+    ProcessorError::from(err).in_phase(ScanPhase::Metadata)
+  })
+}
+
 pub struct FileProcessor {
   acoustid: Arc<AcoustId>,
   conn: Arc<DatabaseConnection>,
+  metadata: Arc<MetadataReaders>,
+  moves: Option<Rc<MoveDetector>>,
+
+  // Lowest combined score of an AcoustID match stored without review
+  min_score: f64,
 
   thread_pool: CpuPool,
 }
 
 impl FileProcessor {
-  pub fn new(acoustid: &Arc<AcoustId>, conn: &Arc<DatabaseConnection>, thread_pool: CpuPool) -> Self {
+  pub fn new(acoustid: &Arc<AcoustId>, conn: &Arc<DatabaseConnection>, metadata: &Arc<MetadataReaders>, min_score: f64, thread_pool: CpuPool) -> Self {
     let acoustid = Arc::clone(acoustid);
     let conn = Arc::clone(conn);
+    let metadata = Arc::clone(metadata);
 
     Self {
       acoustid,
       conn,
+      metadata,
+      moves: None,
+
+      min_score,
 
       thread_pool,
     }
   }
 
+  // Match new files against the given missing entries before inserting them
+  pub fn with_moves(mut self, moves: &Rc<MoveDetector>) -> Self {
+    self.moves = Some(Rc::clone(moves));
+    self
+  }
+
   pub fn call(self, path: String) -> Box<Future<Item = MediaFileInfo, Error = ProcessorError>> {
     // Get the previous value from the database if it exists
-    let fetch_future = wrap_err!(self.conn.fetch_file(path.clone()));
+    let fetch_future = wrap_err!(self.conn.fetch_file(path.clone()), ScanPhase::Database);
 
     // If there is an entry in the database corresponding to the provided file-
     // path, then check if the mtime has changed.
     //
     // If there is no entry in the database, then check if this is a valid file
-    // by checking if `NewMediaFileInfo::read_file(path)` returns a Some value
+    // by checking if `MetadataReaders::read(path)` returns a Some value
     let future = fetch_future.and_then(move |db_info| match db_info {
       Some(v) => self.check_if_update_needed(path, v),
          None => self.insert_path_entry(path),
@@ -55,59 +126,131 @@ impl FileProcessor {
     Box::new(future)
   }
 
-  fn insert_path_entry(self, path: String) -> Box<Future<Item = MediaFileInfo, Error = ProcessorError>> {
-    let conn = Arc::clone(&self.conn);
+  // Process a file using the state of its library entry loaded ahead of time
+  // by `DatabaseConnection::load_root_state`, `None` meaning there is no entry
+  //
+  // Only files that are new, changed, read by an older `METADATA_VERSION`, not
+  // yet indexed or due for an AcoustID check cost a database query. Anything else resolves to
+  // `ProcessorError::Unchanged` without any work.
+  pub fn call_with_state(self, path: String, state: Option<ExistingFile>) -> Box<Future<Item = MediaFileInfo, Error = ProcessorError>> {
+    let state = match state {
+      Some(v) => v,
+      None => return self.insert_path_entry(path),
+    };
+
+    let mtime = match get_mtime(&path) {
+      Ok(v) => v,
+      Err(err) => return Box::new(future::err(err)),
+    };
 
+    let needs_acoustid = acoustid_lookup_due(state.mbid, state.tagged_mbid, state.last_check);
+    let outdated = state.metadata_version < METADATA_VERSION;
+    if mtime == state.mtime && !outdated && !needs_acoustid && state.indexed_hash.is_some() {
+      trace!("id: {}, path: {}, unchanged", state.id, path);
+      return Box::new(future::err(ProcessorError::Unchanged));
+    }
+
+    self.call(path)
+  }
+
+  fn insert_path_entry(self, path: String) -> Box<Future<Item = MediaFileInfo, Error = ProcessorError>> {
     // Only insert entry into database if it is a valid file
     let future = self.read_file_info(&path)
-      .and_then(move |info| {
-        // Log the path after reading the file so invalid files are not printed
-        info!("new file: {}", info.path);
+      .and_then(move |info| match self.moves.clone() {
+        Some(ref moves) if !moves.is_empty() => self.check_moved(info, moves),
+        _ => self.insert_new_entry(info),
+      });
 
-        wrap_err!(conn.insert_file(&info))
-      })
+    Box::new(future)
+  }
+
+  // Check whether a new file is an entry whose file went missing, updating
+  // that entry in place if it is
+  fn check_moved(self, info: NewMediaFileInfo, moves: &Rc<MoveDetector>) -> Box<Future<Item = MediaFileInfo, Error = ProcessorError>> {
+    let moves = Rc::clone(moves);
+
+    let future = wrap_err!(self.conn.fetch_files_by_duration(info.duration, info.track_number), ScanPhase::Database)
+      .and_then(move |candidates| match moves.claim(&info, candidates) {
+        Some(db_info) => self.move_path_entry(info, db_info),
+        None => self.insert_new_entry(info),
+      });
+
+    Box::new(future)
+  }
+
+  fn move_path_entry(self, info: NewMediaFileInfo, db_info: MediaFileInfo) -> Box<Future<Item = MediaFileInfo, Error = ProcessorError>> {
+    info!("id: {}, moved: {} -> {}", db_info.id, db_info.path, info.path);
+
+    let future = wrap_err!(self.conn.update_file(db_info.id, info), ScanPhase::Database)
+      .and_then(move |db_info| self.resolve_mbid(db_info));
+
+    Box::new(future)
+  }
+
+  fn insert_new_entry(self, info: NewMediaFileInfo) -> Box<Future<Item = MediaFileInfo, Error = ProcessorError>> {
+    // Log the path after reading the file so invalid files are not printed
+    info!("new file: {}", info.path);
+
+    let future = wrap_err!(self.conn.insert_file(&info), ScanPhase::Database)
       .and_then(move |info| {
-        let id = info.id;
-        let conn = Arc::clone(&self.conn);
+        let stored: Box<Future<Item = MediaFileInfo, Error = ProcessorError>> = match info.tagged_mbid {
+          Some(mbid) => self.store_tagged_mbid(info, mbid),
+          None => Box::new(future::ok(info)),
+        };
 
-        let last_check = wrap_err!(self.conn.add_acoustid_last_check(id, Utc::now()));
-        let acoustid = self.acoustid.parse_file(&path)
-          .and_then(move |mbid| {
-            wrap_err!(conn.update_file_uuid(id, mbid))
-          })
-          .or_else(|err| match err {
-            ProcessorError::NoFingerprintMatch => Ok(()),
-            _ => Err(err),
-          });
-
-        last_check
-          .join(acoustid)
-          .and_then(|(_, _)| Ok(info))
+        stored.and_then(move |info| self.lookup_new_entry(info))
       });
 
     Box::new(future)
   }
 
+  // Look up a new entry on AcoustID, tagged or not. Its fingerprint was never
+  // checked, so there is no last check or review to look at.
+  fn lookup_new_entry(self, mut info: MediaFileInfo) -> impl Future<Item = MediaFileInfo, Error = ProcessorError> {
+    let id = info.id;
+
+    let conn = Arc::clone(&self.conn);
+
+    // The check is only recorded once AcoustID answered, so a failed lookup
+    // is retried by the next scan
+    self.lookup_mbid(&info)
+      .and_then(move |matched| {
+        wrap_err!(conn.add_acoustid_last_check(id, Utc::now()), ScanPhase::Database)
+          .map(move |_| matched)
+      })
+      .and_then(move |matched| {
+        if let Some((mbid, release)) = matched {
+          info.set_fingerprint_recording(mbid, release);
+        }
+
+        Ok(info)
+      })
+  }
+
   fn read_file_info(&self, path: &str) -> impl Future<Item = NewMediaFileInfo, Error = ProcessorError> {
+    let metadata = Arc::clone(&self.metadata);
     let path = path.to_string();
 
     self.thread_pool.spawn_fn(move || {
       // A None value indicates a non-valid file
-      NewMediaFileInfo::read_file(&path).ok_or(ProcessorError::NothingUseful)
-    })
+      metadata.read(&path).ok_or(ProcessorError::NothingUseful)
+    }).map_err(|e| e.in_phase(ScanPhase::Metadata))
   }
 
   fn check_if_update_needed(self, path: String, db_info: MediaFileInfo) -> Box<Future<Item = MediaFileInfo, Error = ProcessorError>> {
-    let mtime = NewMediaFileInfo::get_mtime(&path);
-    if mtime != db_info.mtime {
+    let mtime = match get_mtime(&path) {
+      Ok(v) => v,
+      Err(err) => return Box::new(future::err(err)),
+    };
+
+    // Entries read by an older version may be missing what is read now
+    if mtime != db_info.mtime || db_info.metadata_version < METADATA_VERSION {
       Box::new(
         self.read_file_info(&path)
           .and_then(move |info| self.update_path_entry(info, db_info))
       )
-    } else if db_info.mbid == None {
-      Box::new(self.handle_acoustid(db_info))
     } else {
-      Box::new(future::ok(db_info))
+      self.resolve_mbid(db_info)
     }
   }
 
@@ -131,81 +274,346 @@ impl FileProcessor {
 
     // Update the database with the file metadata read from the actual file
     // if the database entry differs from the read file metadata. The
-    // modification time field is skipped since it was checked in
-    // `check_if_update_needed(path, db_info)`.
-    let needs_update = check_fields!(title, artist, album, track, track_number, duration);
+    // modification time is included so a file whose tags did not change is
+    // not read again on every scan.
+    let needs_update = check_fields!(mtime, file_size, title, artist, album, track, track_number, duration, tagged_mbid, tagged_release_id, tagged_release_group_id, isrc,
+      album_artist, disc_number, disc_total, track_total, date, original_date, genres, composer, label, catalog_number, comment,
+      container_format, codec, codec_profile, lossless, bitrate, vbr, sample_rate, bit_depth, channels, channel_layout, encoder, encoder_settings,
+      metadata_version);
     let update_future: Box<Future<Item = MediaFileInfo, Error = ProcessorError>> = if needs_update {
       info!("not equal, info: {:#?}, db_info: {:#?}", info, db_info);
 
+      is_field_not_equal!(mtime);
+      is_field_not_equal!(file_size);
       is_field_not_equal!(title);
       is_field_not_equal!(artist);
       is_field_not_equal!(album);
       is_field_not_equal!(track);
       is_field_not_equal!(track_number);
       is_field_not_equal!(duration);
+      is_field_not_equal!(tagged_mbid);
+      is_field_not_equal!(tagged_release_id);
+      is_field_not_equal!(tagged_release_group_id);
+      is_field_not_equal!(isrc);
+      is_field_not_equal!(album_artist);
+      is_field_not_equal!(disc_number);
+      is_field_not_equal!(disc_total);
+      is_field_not_equal!(track_total);
+      is_field_not_equal!(date);
+      is_field_not_equal!(original_date);
+      is_field_not_equal!(genres);
+      is_field_not_equal!(composer);
+      is_field_not_equal!(label);
+      is_field_not_equal!(catalog_number);
+      is_field_not_equal!(comment);
+      is_field_not_equal!(container_format);
+      is_field_not_equal!(codec);
+      is_field_not_equal!(codec_profile);
+      is_field_not_equal!(lossless);
+      is_field_not_equal!(bitrate);
+      is_field_not_equal!(vbr);
+      is_field_not_equal!(sample_rate);
+      is_field_not_equal!(bit_depth);
+      is_field_not_equal!(channels);
+      is_field_not_equal!(channel_layout);
+      is_field_not_equal!(encoder);
+      is_field_not_equal!(encoder_settings);
+      is_field_not_equal!(metadata_version);
 
       let info = info.clone();
       Box::new(
-        wrap_err!(self.conn.update_file(id, info))
+        wrap_err!(self.conn.update_file(id, info), ScanPhase::Database)
       )
     } else {
       Box::new(future::ok(db_info.clone()))
     };
 
-    // Return early if the entry already has a MusicBrainz ID associated with it
-    //
     // TODO(mbilker): with the mtime check, should the mbid be cleared? Should it only be
     // cleared if the metadata has changed?
-    if let Some(mbid) = db_info.mbid {
-      debug!("id: {}, path: {}, associated mbid: {:?}", db_info.id, db_info.path, mbid);
-      return update_future;
+    let future = update_future.and_then(move |db_info| self.resolve_mbid(db_info));
+    Box::new(future)
+  }
+
+  // Use the recording ID the file is tagged with if there is one, otherwise
+  // look the file up on AcoustID if it has no MusicBrainz ID yet. Tagged
+  // files are still fingerprinted once to check the tag.
+  fn resolve_mbid(self, db_info: MediaFileInfo) -> Box<Future<Item = MediaFileInfo, Error = ProcessorError>> {
+    match (db_info.tagged_mbid, db_info.mbid) {
+      (Some(_), _) => Box::new(self.handle_acoustid(db_info)),
+      (None, Some(mbid)) => {
+        debug!("id: {}, path: {}, associated mbid: {:?}", db_info.id, db_info.path, mbid);
+        Box::new(future::ok(db_info))
+      },
+      (None, None) => {
+        debug!("id: {}, path: {}, no associated mbid", db_info.id, db_info.path);
+        Box::new(self.handle_acoustid(db_info))
+      },
+    }
+  }
+
+  // Store the recording ID the file is tagged with as its MusicBrainz ID
+  fn store_tagged_mbid(&self, mut db_info: MediaFileInfo, mbid: Uuid) -> Box<Future<Item = MediaFileInfo, Error = ProcessorError>> {
+    let release = db_info.tagged_release();
+
+    debug!("id: {}, path: {}, using tagged mbid: {}", db_info.id, db_info.path, mbid);
+    if db_info.mbid_disagrees() {
+      warn!("id: {}, path: {}, tagged mbid: {} differs from AcoustID match: {:?}", db_info.id, db_info.path, mbid, db_info.fingerprint_mbid);
     }
 
-    debug!("id: {}, path: {}, no associated mbid", db_info.id, db_info.path);
+    let future = wrap_err!(self.conn.update_file_uuid(db_info.id, mbid, release.clone()), ScanPhase::Database)
+      .map(move |_| {
+        db_info.set_recording(mbid, release);
+        db_info
+      });
 
-    let future = update_future.and_then(move |db_info| self.handle_acoustid(db_info));
     Box::new(future)
   }
 
-  fn handle_acoustid(self, db_info: MediaFileInfo) -> impl Future<Item = MediaFileInfo, Error = ProcessorError> {
-    let id = db_info.id;
+  // Get the fingerprint of an entry's file, reusing the stored fingerprint
+  // if the file has not been modified since it was computed
+  fn fingerprint(&self, id: i32, path: &str, mtime: DateTime<Utc>) -> impl Future<Item = (f64, String), Error = ProcessorError> {
+    self.acoustid.fingerprint_entry(&self.conn, id, path, mtime)
+  }
 
+  // Look up the file on AcoustID, store every candidate recording and store
+  // the MusicBrainz ID of the best one if it is a clear match, resolving to
+  // the ID and its release if there was one
+  fn lookup_mbid(&self, info: &MediaFileInfo) -> impl Future<Item = Option<(Uuid, ReleaseInfo)>, Error = ProcessorError> {
+    let acoustid = Arc::clone(&self.acoustid);
     let conn = Arc::clone(&self.conn);
+    let info = info.clone();
+    let id = info.id;
+    let tagged = info.tagged_mbid.is_some();
+    let min_score = self.min_score;
+
+    self.fingerprint(id, &info.path, info.mtime)
+      .and_then(move |(duration, fingerprint)| acoustid.lookup_fingerprint(duration, fingerprint))
+      .and_then(move |results| {
+        let (candidates, decision, release) = decide_match(&info, &results, min_score);
+        let conn2 = Arc::clone(&conn);
+
+        wrap_err!(conn.replace_acoustid_candidates(id, candidates), ScanPhase::Database)
+          .and_then(move |_| -> Box<Future<Item = Option<(Uuid, ReleaseInfo)>, Error = ProcessorError>> {
+            match decision {
+              // The tag stays the entry's MusicBrainz ID, the match is only
+              // kept to compare against it
+              MatchDecision::Accept(mbid) if tagged => {
+                debug!("id: {}, fingerprint mbid: {}", id, mbid);
+                if info.tagged_mbid != Some(mbid) {
+                  warn!("id: {}, path: {}, tagged mbid: {:?} differs from AcoustID match: {}", id, info.path, info.tagged_mbid, mbid);
+                }
+
+                Box::new(
+                  wrap_err!(conn2.update_fingerprint_mbid(id, mbid), ScanPhase::Database)
+                    .map(move |_| Some((mbid, release)))
+                )
+              },
+              MatchDecision::Accept(mbid) => {
+                debug!("id: {}, new mbid: {}, release: {:?}", id, mbid, release);
+
+                let update = wrap_err!(conn2.update_fingerprint_uuid(id, mbid, release.clone()), ScanPhase::Database);
+                let clear_review = wrap_err!(conn2.clear_pending_review(id), ScanPhase::Database);
+
+                Box::new(update.join(clear_review).map(move |_| Some((mbid, release))))
+              },
+              MatchDecision::Uncertain if tagged => {
+                debug!("id: {}, path: {}, no clear AcoustID match, keeping tagged mbid", id, info.path);
+                Box::new(future::ok(None))
+              },
+              MatchDecision::Uncertain => {
+                info!("id: {}, path: {}, no clear AcoustID match, leaving for review", id, info.path);
+
+                Box::new(
+                  wrap_err!(conn2.add_pending_review(id), ScanPhase::Database)
+                    .map(|_| None)
+                )
+              },
+              MatchDecision::NoMatch => Box::new(future::ok(None)),
+            }
+          })
+      })
+      .or_else(|err| match *err.root() {
+        ProcessorError::NoFingerprintMatch => Ok(None),
+        _ => Err(err),
+      })
+  }
 
-    wrap_err!(self.conn.get_acoustid_last_check(db_info.clone()))
-      .and_then(move |last_check| -> Box<Future<Item = MediaFileInfo, Error = ProcessorError>> {
-        let now = Utc::now();
-        let difference = now.timestamp() - last_check.unwrap_or_else(|| Utc.timestamp(0, 0)).timestamp();
+  fn handle_acoustid(self, db_info: MediaFileInfo) -> impl Future<Item = MediaFileInfo, Error = ProcessorError> {
+    let id = db_info.id;
 
-        // 2 weeks = 1,209,600 seconds
-        if difference < 1_209_600 {
-          debug!("id: {}, path: {}, last check within 2 weeks, not re-checking", id, db_info.path);
-          return Box::new(future::ok(db_info));
+    let last_check = wrap_err!(self.conn.get_acoustid_last_check(db_info.clone()), ScanPhase::Database);
+    let review = wrap_err!(self.conn.fetch_acoustid_review(id), ScanPhase::Database);
+
+    last_check
+      .join(review)
+      .and_then(move |(last_check, review)| -> Box<Future<Item = MediaFileInfo, Error = ProcessorError>> {
+        // A decided review stands until the entry is reviewed again, even
+        // over a recording ID the file was tagged with since
+        if let Some(review) = review {
+          if !review.is_pending() {
+            debug!("id: {}, path: {}, AcoustID review {}, not re-checking", id, db_info.path, review.status);
+            return Box::new(future::ok(db_info));
+          }
         }
 
-        info!("id: {}, path: {}, checking for mbid match", id, db_info.path);
-        debug!("updating mbid (now: {} - last_check: {:?} = {})", now, last_check, difference);
+        let due = acoustid_lookup_due(db_info.mbid, db_info.tagged_mbid, last_check);
 
-        let fetch_fingerprint = self.acoustid.parse_file(&db_info.path)
-          .and_then(move |mbid| {
-            debug!("id: {}, new mbid: {}", id, mbid);
-            wrap_err!(conn.update_file_uuid(id, mbid))
-          })
-          .or_else(|err| match err {
-            ProcessorError::NoFingerprintMatch => Ok(()),
-            _ => Err(err),
-          });
-
-        let last_check = wrap_err!(match last_check {
-          Some(_) => self.conn.update_acoustid_last_check(id, now),
-             None => self.conn.add_acoustid_last_check(id, now),
-        });
+        let stored: Box<Future<Item = MediaFileInfo, Error = ProcessorError>> = match db_info.tagged_mbid {
+          Some(tagged) if db_info.mbid != Some(tagged) => self.store_tagged_mbid(db_info, tagged),
+          _ => Box::new(future::ok(db_info)),
+        };
+
+        if !due {
+          debug!("id: {}, last check within 2 weeks or of a tagged file, not re-checking", id);
+          return stored;
+        }
 
-        let future = last_check
-          .join(fetch_fingerprint)
-          .and_then(|(_, _)| Ok(db_info));
+        let future = stored.and_then(move |db_info| {
+          let now = Utc::now();
+
+          info!("id: {}, path: {}, checking for mbid match", id, db_info.path);
+          debug!("updating mbid (now: {}, last_check: {:?})", now, last_check);
+
+          let conn = Arc::clone(&self.conn);
+
+          // Lookups that failed, e.g. because AcoustID was unavailable, leave
+          // the last check alone so they are not skipped for 2 weeks
+          self.lookup_mbid(&db_info)
+            .and_then(move |matched| {
+              wrap_err!(match last_check {
+                Some(_) => conn.update_acoustid_last_check(id, now),
+                   None => conn.add_acoustid_last_check(id, now),
+              }, ScanPhase::Database)
+                .map(move |_| matched)
+            })
+            .and_then(move |matched| {
+              let mut db_info = db_info;
+              if let Some((mbid, release)) = matched {
+                db_info.set_fingerprint_recording(mbid, release);
+              }
+
+              Ok(db_info)
+            })
+        });
 
         Box::new(future)
       })
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use tokio_core::reactor::Core;
+
+  use acoustid::rate_limiter;
+  use config::MetadataConfig;
+  use models::{NewMediaFileInfo, ReviewStatus};
+  use test_audio::{Format, Recording};
+  use test_server::{fixture, TestServer};
+
+  static MBID: &'static str = "bdf27e74-cc62-43ae-8eb8-2b40d5c421a5";
+
+  // Entry for the recording of `tests/fixtures/acoustid/batch.json`
+  fn info() -> MediaFileInfo {
+    MediaFileInfo {
+      title:    Some("フラワリングナイト".to_owned()),
+      artist:   Some("TAMUSIC".to_owned()),
+      duration: 214_000,
+      ..MediaFileInfo::test_info(1, NewMediaFileInfo::new("/nonexistent/a.flac", Utc.timestamp(1_517_700_000, 0)))
+    }
+  }
+
+  // How `FileProcessor::lookup_mbid` decides on the results of a stand-in
+  // lookup, the part of it that needs no database
+  #[test]
+  fn test_decide_stand_in_lookup() {
+    let server = TestServer::start(vec![(200, fixture("batch.json"))]);
+
+    let mut core = Core::new().unwrap();
+    let config = server.config(1);
+    let acoustid = AcoustId::new("key".to_owned(), &config, &rate_limiter(&config, &core.handle()), None, CpuPool::new(1), &core.handle());
+
+    let results = core.run(acoustid.lookup_fingerprint(214.0, "AQAAAoEA".to_owned())).unwrap();
+    let (candidates, decision, release) = decide_match(&info(), &results, config.min_score);
+
+    let mbid = Uuid::parse_str(MBID).unwrap();
+    assert_eq!(candidates.len(), 1);
+    assert_eq!(candidates[0].library_id, 1);
+    assert_eq!(decision, MatchDecision::Accept(mbid));
+
+    // The recording came without any releases
+    assert_eq!(release, ReleaseInfo::default());
+
+    // A stricter minimum leaves the same match for review
+    let (_, decision, _) = decide_match(&info(), &results, 1.0);
+    assert_eq!(decision, MatchDecision::Uncertain);
+  }
+
+  // Remove the entry of a recording left over by an earlier run
+  fn remove_entry(core: &mut Core, conn: &DatabaseConnection, path: &str) {
+    if let Some(info) = core.run(conn.fetch_file(path.to_owned())).unwrap() {
+      core.run(conn.delete_acoustid_last_check(info.id).and_then(|_| conn.delete_file(info.id))).unwrap();
+    }
+  }
+
+  // Scan new files against the stand-in and the database at `DATABASE_URL`,
+  // which has to have every migration applied
+  #[test]
+  #[ignore]
+  fn test_file_processor_stand_in() {
+    ::ffmpeg::init().unwrap();
+
+    let server = TestServer::start(vec![
+      (200, fixture("batch.json")),
+      (200, fixture("batch.json")),
+    ]);
+
+    let mut core = Core::new().unwrap();
+    let config = server.config(1);
+    let acoustid = Arc::new(AcoustId::new("key".to_owned(), &config, &rate_limiter(&config, &core.handle()), None, CpuPool::new(1), &core.handle()));
+    let conn = Arc::new(DatabaseConnection::new(CpuPool::new(1), 1));
+    let metadata = Arc::new(MetadataReaders::new(&MetadataConfig::default()));
+    let mbid = Uuid::parse_str(MBID).unwrap();
+
+    // The tone shares nothing with the recording but its fingerprint, so its
+    // combined score is only just under 0.6
+    let accepted = Recording::write(Format::Wav, "processor-accept");
+    remove_entry(&mut core, &conn, accepted.path());
+
+    let processor = FileProcessor::new(&acoustid, &conn, &metadata, 0.5, CpuPool::new(1));
+    let info = core.run(processor.call(accepted.path().to_owned())).unwrap();
+
+    let stored = core.run(conn.fetch_file(accepted.path().to_owned())).unwrap().unwrap();
+    assert_eq!(stored.id, info.id);
+    assert_eq!(stored.mbid, Some(mbid));
+    assert_eq!(stored.fingerprint_mbid, Some(mbid));
+    assert_eq!(core.run(conn.fetch_acoustid_candidates(info.id)).unwrap().len(), 1);
+    assert!(core.run(conn.fetch_fingerprint(info.id)).unwrap().is_some());
+    assert!(core.run(conn.fetch_acoustid_review(info.id)).unwrap().is_none());
+
+    // The same match under the default minimum is left for review, then
+    // accepted
+    let reviewed = Recording::write(Format::Wav, "processor-review");
+    remove_entry(&mut core, &conn, reviewed.path());
+
+    let processor = FileProcessor::new(&acoustid, &conn, &metadata, config.min_score, CpuPool::new(1));
+    let info = core.run(processor.call(reviewed.path().to_owned())).unwrap();
+    assert_eq!(info.mbid, None);
+
+    let review = core.run(conn.fetch_acoustid_review(info.id)).unwrap().unwrap();
+    assert!(review.is_pending());
+    assert_eq!(core.run(conn.fetch_acoustid_candidates(info.id)).unwrap().len(), 1);
+
+    core.run(conn.decide_review(info.id, ReviewStatus::Accepted, Some(mbid))).unwrap();
+    let stored = core.run(conn.fetch_file(reviewed.path().to_owned())).unwrap().unwrap();
+    assert_eq!(stored.mbid, Some(mbid));
+    assert!(!core.run(conn.fetch_acoustid_review(info.id)).unwrap().unwrap().is_pending());
+
+    assert_eq!(server.requests().len(), 2);
+
+    remove_entry(&mut core, &conn, accepted.path());
+    remove_entry(&mut core, &conn, reviewed.path());
+  }
+}
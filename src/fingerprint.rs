@@ -1,4 +1,8 @@
+use std::cmp;
+use std::sync::Arc;
+
 use chromaprint::Chromaprint;
+use chrono::{DateTime, Utc};
 use ffmpeg::ChannelLayout;
 use ffmpeg::decoder::Audio as AudioDecoder;
 use ffmpeg::format::{self, Sample};
@@ -6,15 +10,34 @@ use ffmpeg::format::context::Input;
 use ffmpeg::frame::Audio;
 use ffmpeg::media::Type;
 use ffmpeg::software;
+use ffmpeg::software::resampling;
+use futures::Future;
+use futures::future;
+use futures_cpupool::CpuPool;
+
+use database::DatabaseConnection;
+use models::Fingerprint;
 
 use basic_types::*;
 
 // Maximum duration global from Chromaprint's fpcalc utility
 static MAX_AUDIO_DURATION: f64 = 120.0;
 
+// Highest sample rate passed to Chromaprint as is, higher rates are
+// resampled to `RESAMPLED_SAMPLE_RATE` first
+static MAX_SAMPLE_RATE: u32 = 192_000;
+static RESAMPLED_SAMPLE_RATE: u32 = 44_100;
+
 fn get_best_audio_stream(ictx: &Input) -> Result<(AudioDecoder, f64, usize), ProcessorError> {
   let stream = try!(ictx.streams().best(Type::Audio).ok_or(ProcessorError::NoAudioStream));
-  let duration = stream.duration() as f64 * f64::from(stream.time_base());
+
+  // Streams of some containers have no duration of their own, the
+  // container's is in microseconds
+  let duration = if stream.duration() > 0 {
+    stream.duration() as f64 * f64::from(stream.time_base())
+  } else {
+    ictx.duration() as f64 / 1_000_000.0
+  };
   let index = stream.index();
   debug!("best audio stream index: {}", index);
 
@@ -36,6 +59,49 @@ fn get_best_audio_stream(ictx: &Input) -> Result<(AudioDecoder, f64, usize), Pro
   Ok((decoder, duration, index))
 }
 
+// Convert the frames the decoder has ready and feed them to Chromaprint,
+// returning whether the samples needed for a fingerprint have all been fed
+fn feed_frames(decoder: &mut AudioDecoder, convert: &mut resampling::Context, chroma: &mut Chromaprint, channel_layout: ChannelLayout, channels: i32, remaining: &mut u32) -> Result<bool, ProcessorError> {
+  let mut decoded = Audio::empty();
+
+  // Codecs like Monkey's Audio decode a packet into several frames, keep
+  // receiving until the decoder wants the next packet or has been drained
+  while decoder.receive_frame(&mut decoded).is_ok() {
+    // Frames of WAV files without a channel mask, among others, have no
+    // channel layout and the resampler rejects them as a changed input
+    if decoded.channel_layout().channels() == 0 {
+      decoded.set_channel_layout(channel_layout);
+    }
+
+    let mut processed = Audio::empty();
+    let delay = try!(convert.run(&decoded, &mut processed));
+
+    trace!("samples: {}, delay: {:?}, processed: {:?}", decoded.samples(), delay, processed);
+
+    let frame_size = cmp::min(processed.samples() as u32, *remaining);
+    *remaining -= frame_size;
+
+    if frame_size > 0 {
+      // Feed chromaprint with the audio data
+      //
+      // There is only one plane because the audio data is now interleaved by
+      // the resampler
+      let data_size = (frame_size * channels as u32) as usize;
+      let data = processed.data(0);
+      trace!("data_size: {}, data.len(): {}", data_size, data.len());
+      if !chroma.feed(&data[0..data_size]) {
+        return Err(ProcessorError::Chromaprint("feed returned false"));
+      }
+    }
+
+    if *remaining == 0 {
+      return Ok(true);
+    }
+  }
+
+  Ok(false)
+}
+
 pub fn get(path: &str) -> Result<(f64, String), ProcessorError> {
   debug!("Chromaprint version: {}", Chromaprint::version());
 
@@ -57,96 +123,302 @@ pub fn get(path: &str) -> Result<(f64, String), ProcessorError> {
   debug!("audio.channel_layout: {:?} (channels: {})", decoder.channel_layout(), decoder.channel_layout().channels());
   debug!("audio.frame_start: {:?}", decoder.frame_start());
 
+  // DSD decodes at several hundred kHz, far more than Chromaprint needs
+  // before it downsamples to 11025 Hz itself
+  let out_samplerate = if samplerate > MAX_SAMPLE_RATE {
+    RESAMPLED_SAMPLE_RATE
+  } else {
+    samplerate
+  };
+
   // Setup the converter to signed 16-bit interleaved needed for
   // accurate fingerprints for AcoustID
   let channel_layout = decoder.channel_layout();
   let in_format = (decoder.format(), channel_layout, samplerate);
-  let out_format = (Sample::from("s16"), channel_layout, samplerate);
+  let out_format = (Sample::from("s16"), channel_layout, out_samplerate);
   let mut convert = try!(software::resampler(in_format, out_format));
 
-  // Stream size limit used to count the number of samples for two minutes
-  // of audio based on AcoustID's reference implementation
-  let stream_limit = (MAX_AUDIO_DURATION as u32) * samplerate;
-  let mut stream_size = 0;
-  debug!("stream_limit: {}", stream_limit);
+  // Number of samples for two minutes of audio based on AcoustID's
+  // reference implementation
+  let mut remaining = (MAX_AUDIO_DURATION as u32) * out_samplerate;
+  debug!("stream_limit: {}", remaining);
 
   // Initialize Chromaprint context
   let mut chroma = Chromaprint::new();
-  if !chroma.start(samplerate as i32, channels) {
+  if !chroma.start(out_samplerate as i32, channels) {
     return Err(ProcessorError::Chromaprint("failed to start chromaprint"));
   }
 
-  // Buffer frame for the current decoded packet
-  let mut decoded = Audio::empty();
-
   // Iterate through all the relevant packets based on the stream index
   //
   // I probably would have never figured out how to do this without the
   // reference C implementation.
+  let mut stream_done = false;
   for (stream, packet) in ictx.packets() {
     // Only want packets for the audio stream
     if stream.index() != index {
       continue;
     }
 
-    let decoder_res = try!(decoder.decode(&packet, &mut decoded));
-    if decoder_res != true {
+    // A damaged packet costs a few milliseconds of audio, not the file
+    if let Err(err) = decoder.send_packet(&packet) {
+      warn!("path: {}, skipping packet that failed to decode: {}", path, err);
       continue;
     }
 
-    let mut processed = Audio::empty();
-    let delay = try!(convert.run(&decoded, &mut processed));
+    stream_done = try!(feed_frames(&mut decoder, &mut convert, &mut chroma, channel_layout, channels, &mut remaining));
+    if stream_done {
+      break;
+    }
+  }
+
+  // Files shorter than the limit leave frames buffered in the decoder
+  if !stream_done && decoder.send_eof().is_ok() {
+    try!(feed_frames(&mut decoder, &mut convert, &mut chroma, channel_layout, channels, &mut remaining));
+  }
+
+  let finish_res = chroma.finish();
+  debug!("finish_res: {}", finish_res);
 
-    trace!("packet size: {}, delay: {:?}, processed: {:?}", packet.size(), delay, processed);
+  let fingerprint = try!(chroma.fingerprint().ok_or(ProcessorError::Chromaprint("no fingerprint generated")));
+  debug!("fingerprint: {}", fingerprint);
 
-    let mut frame_size = processed.samples() as u32;
-    let remaining = stream_limit - stream_size;
-    let stream_done = {
-      if frame_size > remaining {
-        frame_size = remaining;
-        true
-      } else {
-        false
+  Ok((duration, fingerprint))
+}
+
+// Get the fingerprint of an entry's file, computed on `pool` unless the
+// stored fingerprint is still current, storing any new one
+pub fn get_stored(pool: &CpuPool, conn: &Arc<DatabaseConnection>, id: i32, path: &str, mtime: DateTime<Utc>) -> impl Future<Item = (f64, String), Error = ProcessorError> {
+  let pool = pool.clone();
+  let conn2 = Arc::clone(conn);
+  let path = path.to_owned();
+
+  conn.fetch_fingerprint(id)
+    .map_err(|e| ProcessorError::from(e).in_phase(ScanPhase::Database))
+    .and_then(move |stored| -> Box<Future<Item = (f64, String), Error = ProcessorError>> {
+      if let Some(stored) = stored {
+        if stored.mtime == mtime {
+          debug!("id: {}, path: {}, reusing stored fingerprint", id, path);
+          return Box::new(future::ok((stored.duration, stored.fingerprint)));
+        }
       }
+
+      let path2 = path.clone();
+      let path3 = path.clone();
+
+      // Eat up fingerprinting errors, I mostly see them when a file is not
+      // easily parsed like WAV files
+      let future = pool.spawn_fn(move || get(&path2))
+        .map_err(|e| e.in_phase(ScanPhase::Fingerprint))
+        .or_else(move |e| match *e.root() {
+          ProcessorError::NoAudioStream => {
+            error!("path: {}, weird case with no audio stream during fingerprinting (bad extension?)", path3);
+            Err(ProcessorError::NoFingerprintMatch)
+          },
+          ProcessorError::FFmpeg(ref err) => {
+            error!("path: {}, ffmpeg error: {}", path3, err);
+            Err(ProcessorError::NoFingerprintMatch)
+          },
+          _ => Err(e),
+        })
+        .and_then(move |(duration, compressed)| -> Box<Future<Item = (f64, String), Error = ProcessorError>> {
+          let fingerprint = match Fingerprint::new(id, mtime, duration, compressed.clone()) {
+            Some(v) => v,
+            None => {
+              warn!("id: {}, path: {}, unable to decode fingerprint, not storing it", id, path);
+              return Box::new(future::ok((duration, compressed)));
+            },
+          };
+
+          Box::new(
+            conn2.save_fingerprint(fingerprint)
+              .map_err(|e| ProcessorError::from(e).in_phase(ScanPhase::Database))
+              .map(move |_| (duration, compressed))
+          )
+        });
+
+      Box::new(future)
+    })
+}
+
+// Chromaprint's compressed fingerprint format stores the position of each
+// set bit as a delta from the previous one, 3 bits per delta, with larger
+// deltas continued in a second array of 5 bit values
+static NORMAL_BITS: usize = 3;
+static EXCEPTION_BITS: usize = 5;
+static MAX_NORMAL_VALUE: u8 = 7;
+
+// Compressed fingerprints use the URL-safe base64 alphabet without padding
+fn decode_base64(input: &str) -> Option<Vec<u8>> {
+  let mut output = Vec::with_capacity(input.len() * 3 / 4);
+  let mut buffer: u32 = 0;
+  let mut bits = 0;
+
+  for c in input.bytes() {
+    let value = match c {
+      b'A'...b'Z' => c - b'A',
+      b'a'...b'z' => c - b'a' + 26,
+      b'0'...b'9' => c - b'0' + 52,
+      b'-' => 62,
+      b'_' => 63,
+      _ => return None,
     };
-    stream_size += frame_size;
 
-    if frame_size == 0 && !stream_done {
-      continue;
+    buffer = (buffer << 6) | u32::from(value);
+    bits += 6;
+
+    if bits >= 8 {
+      bits -= 8;
+      output.push((buffer >> bits) as u8);
+      buffer &= (1 << bits) - 1;
     }
+  }
 
-    if frame_size > 0 {
-      // Feed chromaprint with the audio data
-      //
-      // There is only one plane because the audio data is now interleaved by
-      // the resampler
-      let data_size = (frame_size * channels as u32) as usize;
-      let data = processed.data(0);
-      trace!("data_size: {}, data.len(): {}", data_size, data.len());
-      if !chroma.feed(&data[0..data_size]) {
-        return Err(ProcessorError::Chromaprint("feed returned false"));
+  Some(output)
+}
+
+// Split `data` into `width` bit values, least significant bit first
+fn unpack_ints(data: &[u8], width: usize) -> Vec<u8> {
+  let count = data.len() * 8 / width;
+
+  (0..count)
+    .map(|i| {
+      let mut value = 0;
+      for bit in 0..width {
+        let pos = i * width + bit;
+        if (data[pos / 8] >> (pos % 8)) & 1 == 1 {
+          value |= 1 << bit;
+        }
       }
-    }
 
-    if stream_done {
-      break;
+      value
+    })
+    .collect()
+}
+
+// Decode a compressed fingerprint as returned by `get` into the Chromaprint
+// algorithm it was generated with and its raw form, the form needed to
+// compare fingerprints with each other
+pub fn decode(fingerprint: &str) -> Option<(i32, Vec<u32>)> {
+  let data = decode_base64(fingerprint)?;
+  if data.len() < 4 {
+    return None;
+  }
+
+  let algorithm = i32::from(data[0]);
+  let count = (usize::from(data[1]) << 16) | (usize::from(data[2]) << 8) | usize::from(data[3]);
+  if count == 0 {
+    return Some((algorithm, Vec::new()));
+  }
+
+  // A zero ends each value, find where the last value ends and how many
+  // deltas continue in the exception array
+  let mut bits = unpack_ints(&data[4..], NORMAL_BITS);
+  let mut found = 0;
+  let mut exceptions = 0;
+  let mut end = None;
+
+  for (i, &bit) in bits.iter().enumerate() {
+    if bit == 0 {
+      found += 1;
+      if found == count {
+        end = Some(i + 1);
+        break;
+      }
+    } else if bit == MAX_NORMAL_VALUE {
+      exceptions += 1;
     }
   }
 
-  let finish_res = chroma.finish();
-  debug!("finish_res: {}", finish_res);
+  let end = end?;
+  bits.truncate(end);
 
-  let fingerprint = try!(chroma.fingerprint().ok_or(ProcessorError::Chromaprint("no fingerprint generated")));
-  debug!("fingerprint: {}", fingerprint);
+  let offset = 4 + (end * NORMAL_BITS + 7) / 8;
+  let exceptional = unpack_ints(data.get(offset..)?, EXCEPTION_BITS);
+  if exceptional.len() < exceptions {
+    return None;
+  }
+  let mut exceptional = exceptional.into_iter();
 
-  Ok((duration, fingerprint))
+  // Each value is stored XORed with the previous one
+  let mut values: Vec<u32> = Vec::with_capacity(count);
+  let mut value = 0;
+  let mut last_bit = 0;
+
+  for bit in bits {
+    let mut bit = u32::from(bit);
+
+    if bit == 0 {
+      let previous = values.last().cloned().unwrap_or(0);
+      values.push(value ^ previous);
+
+      value = 0;
+      last_bit = 0;
+      continue;
+    }
+
+    if bit == u32::from(MAX_NORMAL_VALUE) {
+      bit += u32::from(exceptional.next()?);
+    }
+
+    bit += last_bit;
+    last_bit = bit;
+
+    if bit > 32 {
+      return None;
+    }
+    value |= 1 << (bit - 1);
+  }
+
+  Some((algorithm, values))
 }
 
 #[cfg(test)]
 mod tests {
+  use super::*;
+  use test_audio::{self, Recording, DURATION, FORMATS};
+
+  fn assert_fingerprinted(path: &str, context: &str) {
+    let (duration, fingerprint) = get(path).unwrap_or_else(|err| panic!("{}: {:#?}", context, err));
+    assert!((duration - f64::from(DURATION)).abs() <= 0.1, "{}: duration {}", context, duration);
+
+    let (_, values) = decode(&fingerprint).unwrap_or_else(|| panic!("{}: invalid fingerprint {}", context, fingerprint));
+    assert!(!values.is_empty(), "{}: empty fingerprint", context);
+  }
+
   #[test]
-  fn test_get() {
-    // TODO(mbilker): compose a few example known good fingerprints and
-    // assert_eq! them to values from calling get
+  fn test_decode() {
+    assert_eq!(decode("AQAAAoEA"), Some((1, vec![1, 3])));
+  }
+
+  #[test]
+  fn test_decode_exception() {
+    assert_eq!(decode("AQAAAQcD"), Some((1, vec![512])));
+  }
+
+  #[test]
+  fn test_decode_invalid() {
+    assert_eq!(decode("AQ"), None);
+    assert_eq!(decode("AQAAAg=="), None);
+  }
+
+  #[test]
+  fn test_get_formats() {
+    ::ffmpeg::init().unwrap();
+
+    for &format in FORMATS {
+      let recording = Recording::write(format, "fingerprint");
+      assert_fingerprinted(recording.path(), &format!("{:?}", format));
+    }
+  }
+
+  // Monkey's Audio, WavPack and TTA files are read from `tests/fixtures/audio`
+  #[test]
+  fn test_get_encoded_formats() {
+    ::ffmpeg::init().unwrap();
+
+    for extension in test_audio::ENCODED_EXTENSIONS {
+      assert_fingerprinted(&test_audio::encoded_fixture(extension), extension);
+    }
   }
 }
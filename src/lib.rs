@@ -10,14 +10,15 @@ extern crate dotenv;
 extern crate elastic;
 extern crate fallible_iterator;
 extern crate ffmpeg;
+extern crate flate2;
 extern crate futures;
 extern crate futures_cpupool;
 extern crate hyper;
 extern crate hyper_tls;
 extern crate mediainfo;
+extern crate notify;
 extern crate postgres;
 extern crate r2d2;
-extern crate ratelimit;
 extern crate serde;
 extern crate serde_yaml;
 extern crate tokio_core;
@@ -33,12 +34,25 @@ extern crate walkdir;
 
 pub mod acoustid;
 pub mod basic_types;
+pub mod bulk_indexer;
 pub mod config;
 pub mod database;
+pub mod duplicates;
 pub mod elasticsearch;
 pub mod scanner;
 pub mod file_processor;
 pub mod fingerprint;
+pub mod matcher;
+pub mod metadata;
 pub mod models;
+pub mod move_detector;
 pub mod processor;
+pub mod rate_limiter;
+pub mod response_cache;
+pub mod review;
 pub mod schema;
+pub mod submitter;
+pub mod watcher;
+
+#[cfg(test)] mod test_audio;
+#[cfg(test)] mod test_server;
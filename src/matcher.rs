@@ -0,0 +1,271 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+use uuid::Uuid;
+
+use models::{MediaFileInfo, NewAcoustIdCandidate, ReleaseInfo};
+
+// Weights of the parts of a candidate's combined score: the AcoustID score,
+// how close the recording's duration is to the file's and how similar its
+// title and artists are to the file's tags
+static SCORE_WEIGHT: f64 = 0.6;
+static DURATION_WEIGHT: f64 = 0.2;
+static TAGS_WEIGHT: f64 = 0.2;
+
+// Part given for a duration or tags that cannot be compared
+static UNKNOWN_PART: f64 = 0.5;
+
+// Durations this many seconds apart still count as the same, and this many
+// seconds apart or more as unrelated
+static DURATION_EXACT: f64 = 2.0;
+static DURATION_MAX: f64 = 15.0;
+
+// How far the best recording has to be ahead of the next one
+static MIN_MARGIN: f64 = 0.05;
+
+// A release a recording appears on, with the title it is compared to the
+// file's album tag by
+#[derive(Clone, Debug)]
+pub struct ReleaseChoice {
+  pub title: Option<String>,
+  pub release: ReleaseInfo,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MatchDecision {
+  Accept(Uuid),
+
+  // Candidates that are too weak or too close to call, left for review
+  Uncertain,
+
+  NoMatch,
+}
+
+fn words(s: &str) -> HashSet<String> {
+  s.to_lowercase()
+    .split(|c: char| !c.is_alphanumeric())
+    .filter(|word| !word.is_empty())
+    .map(|word| word.to_owned())
+    .collect()
+}
+
+// Fraction of words shared by both, `None` if either has no words
+fn similarity(a: Option<&str>, b: Option<&str>) -> Option<f64> {
+  let (a, b) = match (a, b) {
+    (Some(a), Some(b)) => (words(a), words(b)),
+    _ => return None,
+  };
+
+  if a.is_empty() || b.is_empty() {
+    return None;
+  }
+
+  let shared = a.intersection(&b).count();
+  let total = a.union(&b).count();
+
+  Some(shared as f64 / total as f64)
+}
+
+fn duration_part(file_duration: u32, duration: Option<i32>) -> f64 {
+  let duration = match duration {
+    Some(v) => f64::from(v),
+    None => return UNKNOWN_PART,
+  };
+
+  let difference = (f64::from(file_duration) / 1000.0 - duration).abs();
+  if difference <= DURATION_EXACT {
+    1.0
+  } else if difference >= DURATION_MAX {
+    0.0
+  } else {
+    1.0 - (difference - DURATION_EXACT) / (DURATION_MAX - DURATION_EXACT)
+  }
+}
+
+fn tags_part(info: &MediaFileInfo, candidate: &NewAcoustIdCandidate) -> f64 {
+  let parts: Vec<f64> = vec![
+    similarity(info.title.as_ref().map(|s| s.as_str()), candidate.title.as_ref().map(|s| s.as_str())),
+    similarity(info.artist.as_ref().map(|s| s.as_str()), candidate.artists.as_ref().map(|s| s.as_str())),
+  ].into_iter().filter_map(|part| part).collect();
+
+  if parts.is_empty() {
+    UNKNOWN_PART
+  } else {
+    parts.iter().sum::<f64>() / parts.len() as f64
+  }
+}
+
+// Combined score of a candidate for a library entry, between 0 and 1
+pub fn combined_score(info: &MediaFileInfo, candidate: &NewAcoustIdCandidate) -> f64 {
+  SCORE_WEIGHT * f64::from(candidate.score) +
+  DURATION_WEIGHT * duration_part(info.duration, candidate.duration) +
+  TAGS_WEIGHT * tags_part(info, candidate)
+}
+
+// Each recording among the candidates with its best combined score, best
+// first
+pub fn rank(info: &MediaFileInfo, candidates: &[NewAcoustIdCandidate]) -> Vec<(Uuid, f64)> {
+  let mut ranked: Vec<(Uuid, f64)> = Vec::new();
+
+  for candidate in candidates {
+    let score = combined_score(info, candidate);
+
+    match ranked.iter().position(|&(id, _)| id == candidate.recording_id) {
+      Some(i) => if score > ranked[i].1 {
+        ranked[i].1 = score;
+      },
+      None => ranked.push((candidate.recording_id, score)),
+    };
+  }
+
+  ranked.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+  ranked
+}
+
+// Accept the best recording if its combined score is at least `min_score`
+// and it is clearly ahead of the rest
+pub fn decide(ranked: &[(Uuid, f64)], min_score: f64) -> MatchDecision {
+  let (id, best) = match ranked.first() {
+    Some(&v) => v,
+    None => return MatchDecision::NoMatch,
+  };
+
+  let runner_up = ranked.get(1).map(|&(_, score)| score).unwrap_or(0.0);
+  if best >= min_score && best - runner_up >= MIN_MARGIN {
+    MatchDecision::Accept(id)
+  } else {
+    MatchDecision::Uncertain
+  }
+}
+
+fn release_score(info: &MediaFileInfo, choice: &ReleaseChoice) -> f64 {
+  let title = similarity(info.album.as_ref().map(|s| s.as_str()), choice.title.as_ref().map(|s| s.as_str()))
+    .unwrap_or(UNKNOWN_PART);
+  let track = match choice.release.track_position {
+    Some(position) if info.track_number != 0 && position as u32 == info.track_number => 1.0,
+    _ => 0.0,
+  };
+
+  title + track / 2.0
+}
+
+// The release a file most likely came from: the one whose title is closest
+// to the file's album tag, then the one with the file's track number, then
+// the earliest
+pub fn best_release(info: &MediaFileInfo, choices: Vec<ReleaseChoice>) -> Option<ReleaseInfo> {
+  let mut scored: Vec<(f64, ReleaseChoice)> = choices.into_iter()
+    .map(|choice| (release_score(info, &choice), choice))
+    .collect();
+
+  scored.sort_by(|a, b| {
+    b.0.partial_cmp(&a.0)
+      .unwrap_or(Ordering::Equal)
+      .then_with(|| match (&a.1.release.release_date, &b.1.release.release_date) {
+        (&Some(ref a), &Some(ref b)) => a.cmp(b),
+        (&Some(_), &None)            => Ordering::Less,
+        (&None, &Some(_))            => Ordering::Greater,
+        (&None, &None)               => Ordering::Equal,
+      })
+  });
+
+  scored.into_iter().next().map(|(_, choice)| choice.release)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use chrono::{TimeZone, Utc};
+
+  use models::NewMediaFileInfo;
+
+  fn info() -> MediaFileInfo {
+    MediaFileInfo {
+      title:        Some("Flowering Night".to_owned()),
+      artist:       Some("TAMUSIC".to_owned()),
+      album:        Some("Touhou Jazz Arrange".to_owned()),
+      track_number: 1,
+      duration:     215_000,
+      ..MediaFileInfo::test_info(1, NewMediaFileInfo::new("/nonexistent/a.flac", Utc.timestamp(1_517_700_000, 0)))
+    }
+  }
+
+  fn candidate(recording: u8, score: f32, title: &str, duration: i32) -> NewAcoustIdCandidate {
+    NewAcoustIdCandidate {
+      library_id:   1,
+      acoustid_id:  "f2451269-9fec-4e82-aaf8-0bdf1f069ecf".to_owned(),
+      score:        score,
+      recording_id: Uuid::from_bytes(&[recording; 16]).unwrap(),
+      title:        Some(title.to_owned()),
+      artists:      Some("TAMUSIC".to_owned()),
+      duration:     Some(duration),
+
+      release_id:       None,
+      release_group_id: None,
+      medium_position:  None,
+      track_position:   None,
+      release_date:     None,
+    }
+  }
+
+  fn release(id: u8, title: &str, track: i32, date: &str) -> ReleaseChoice {
+    ReleaseChoice {
+      title: Some(title.to_owned()),
+      release: ReleaseInfo {
+        release_id:       Some(Uuid::from_bytes(&[id; 16]).unwrap()),
+        release_group_id: None,
+        medium_position:  Some(1),
+        track_position:   Some(track),
+        release_date:     Some(date.to_owned()),
+      },
+    }
+  }
+
+  #[test]
+  fn test_accept_clear_match() {
+    let candidates = vec![
+      candidate(1, 0.95, "Flowering Night", 215),
+      candidate(2, 0.95, "Something Else", 300),
+    ];
+
+    let ranked = rank(&info(), &candidates);
+    assert_eq!(decide(&ranked, 0.7), MatchDecision::Accept(candidates[0].recording_id));
+    assert_eq!(decide(&ranked, 0.99), MatchDecision::Uncertain);
+  }
+
+  #[test]
+  fn test_uncertain_when_ambiguous() {
+    let candidates = vec![
+      candidate(1, 0.95, "Flowering Night", 215),
+      candidate(2, 0.95, "Flowering Night", 214),
+    ];
+
+    assert_eq!(decide(&rank(&info(), &candidates), 0.7), MatchDecision::Uncertain);
+  }
+
+  #[test]
+  fn test_uncertain_when_weak() {
+    let candidates = vec![candidate(1, 0.3, "Something Else", 180)];
+
+    assert_eq!(decide(&rank(&info(), &candidates), 0.7), MatchDecision::Uncertain);
+  }
+
+  #[test]
+  fn test_no_candidates() {
+    assert_eq!(decide(&rank(&info(), &[]), 0.7), MatchDecision::NoMatch);
+  }
+
+  #[test]
+  fn test_best_release() {
+    let choices = vec![
+      release(1, "Touhou Best Of", 7, "2009"),
+      release(2, "Touhou Jazz Arrange", 3, "2012-05-01"),
+      release(3, "Touhou Jazz Arrange", 1, "2011-08-12"),
+      release(4, "Touhou Jazz Arrange", 1, "2010-12-30"),
+    ];
+
+    let best = best_release(&info(), choices).unwrap();
+    assert_eq!(best.release_id, Some(Uuid::from_bytes(&[4; 16]).unwrap()));
+    assert_eq!(best_release(&info(), Vec::new()), None);
+  }
+}
@@ -0,0 +1,647 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use ffmpeg::{codec, DictionaryRef};
+use ffmpeg::format;
+use ffmpeg::media::Type;
+use mediainfo::{MediaInfo, MediaInfoInfo, MediaInfoStream};
+use uuid::Uuid;
+
+use config::MetadataConfig;
+use models::NewMediaFileInfo;
+
+use basic_types::*;
+
+// Extensions of files that are never catalogued, playlists have a duration
+// according to both readers and backups duplicate the original files
+static IGNORED_EXTENSIONS: &'static [&'static str] = &[
+  "m3u8",
+  "mpls",
+  "orig",
+  "bak",
+];
+
+// Version of what the readers read from files, stored on each entry. Raise it
+// whenever the readers read something new or differently, so entries read
+// before are read again by the next scan even though their files did not
+// change.
+//
+// 1: tagged IDs, tags and audio properties
+// 2: bit depth of the source rather than of the decoded samples with ffmpeg
+pub static METADATA_VERSION: i32 = 2;
+
+// What a reader made of a file
+pub enum ReadResult {
+  Read(NewMediaFileInfo),
+
+  // The reader found no audio in the file, the next reader is tried
+  Declined,
+
+  // The file is a playlist or backup, no other reader is tried
+  Excluded,
+}
+
+impl ReadResult {
+  pub fn info(self) -> Option<NewMediaFileInfo> {
+    match self {
+      ReadResult::Read(info) => Some(info),
+      ReadResult::Declined | ReadResult::Excluded => None,
+    }
+  }
+}
+
+// Reads the tags and audio properties of a file
+//
+// `Err` means the reader could not make sense of the file and the next
+// reader is tried.
+pub trait MetadataReader {
+  fn name(&self) -> &'static str;
+
+  fn read(&self, path: &str, mtime: DateTime<Utc>) -> Result<ReadResult, ProcessorError>;
+}
+
+// Reader for a name used in the configuration
+pub fn reader(name: &str) -> Option<Arc<MetadataReader + Send + Sync>> {
+  match name {
+    "mediainfo" => Some(Arc::new(MediaInfoReader)),
+    "ffmpeg"    => Some(Arc::new(FfmpegReader)),
+    _ => None,
+  }
+}
+
+// Leading number of a tag like `3` or `3/12`
+pub fn parse_number(value: &str) -> Option<i32> {
+  let digits: String = value.trim().chars().take_while(|c| c.is_digit(10)).collect();
+  digits.parse().ok()
+}
+
+// Total of a tag like `3/12`
+fn parse_total(value: &str) -> Option<i32> {
+  value.splitn(2, '/').nth(1).and_then(parse_number)
+}
+
+// Genres of a tag holding several, separated like `Jazz / Touhou` or
+// `Jazz; Touhou`
+fn split_genres(value: &str) -> Vec<String> {
+  value.split(|c| c == '/' || c == ';')
+    .map(|genre| genre.trim())
+    .filter(|genre| !genre.is_empty())
+    .map(|genre| genre.to_owned())
+    .collect()
+}
+
+// Readers tried in turn for each file, in the order set by the configuration
+// for the file's extension
+pub struct MetadataReaders {
+  readers: Vec<Arc<MetadataReader + Send + Sync>>,
+  formats: HashMap<String, Vec<Arc<MetadataReader + Send + Sync>>>,
+}
+
+impl MetadataReaders {
+  // Names in the configuration are checked by `Config::read_configuration`
+  pub fn new(config: &MetadataConfig) -> Self {
+    let readers = |names: &[String]| -> Vec<Arc<MetadataReader + Send + Sync>> {
+      names.iter().filter_map(|name| reader(name)).collect()
+    };
+
+    Self {
+      readers: readers(&config.readers),
+      formats: config.formats.iter()
+        .map(|(extension, names)| (extension.to_lowercase(), readers(names)))
+        .collect(),
+    }
+  }
+
+  // Tags and audio properties of a file, `None` for files that are not music
+  // files or that no reader could open
+  pub fn read(&self, path: &str) -> Option<NewMediaFileInfo> {
+    let extension = Path::new(path).extension()
+      .and_then(|extension| extension.to_str())
+      .map(|extension| extension.to_lowercase());
+
+    if let Some(ref extension) = extension {
+      if IGNORED_EXTENSIONS.contains(&extension.as_str()) {
+        trace!("ignoring {} extension", extension);
+        return None;
+      }
+    }
+
+    let mtime = match NewMediaFileInfo::get_mtime(path) {
+      Some(v) => v,
+      None => {
+        error!("unable to get modification time for path: {}", path);
+        return None;
+      },
+    };
+
+    let readers = extension.as_ref()
+      .and_then(|extension| self.formats.get(extension))
+      .unwrap_or(&self.readers);
+
+    let mut declined = false;
+
+    for reader in readers {
+      match reader.read(path, mtime) {
+        Ok(ReadResult::Read(info)) => {
+          if info.is_default_values() {
+            return None;
+          }
+
+          return Some(info);
+        },
+        Ok(ReadResult::Declined) => {
+          trace!("path: {}, {} reader found no audio", path, reader.name());
+          declined = true;
+        },
+        Ok(ReadResult::Excluded) => return None,
+        Err(err) => warn!("path: {}, {} reader failed: {}", path, reader.name(), err),
+      };
+    }
+
+    // Files every reader opened without finding audio in are not music files
+    if !declined {
+      error!("could not open file: {}", path);
+    }
+
+    None
+  }
+}
+
+// Names MediaInfo gives the MusicBrainz recording ID tag in the different
+// tag formats
+static RECORDING_ID_TAGS: &'static [&'static str] = &[
+  "MUSICBRAINZ_TRACKID",
+  "MusicBrainz Track Id",
+  "MUSICBRAINZ TRACK ID",
+];
+
+static RELEASE_ID_TAGS: &'static [&'static str] = &[
+  "MUSICBRAINZ_ALBUMID",
+  "MusicBrainz Album Id",
+  "MUSICBRAINZ ALBUM ID",
+];
+
+static RELEASE_GROUP_ID_TAGS: &'static [&'static str] = &[
+  "MUSICBRAINZ_RELEASEGROUPID",
+  "MusicBrainz Release Group Id",
+  "MUSICBRAINZ RELEASE GROUP ID",
+];
+
+static ISRC_TAGS: &'static [&'static str] = &[
+  "ISRC",
+  "TSRC",
+];
+
+static DATE_TAGS: &'static [&'static str] = &[
+  "Recorded_Date",
+  "Released_Date",
+];
+
+static ORIGINAL_DATE_TAGS: &'static [&'static str] = &[
+  "Original/Released_Date",
+  "ORIGINALDATE",
+  "ORIGINALYEAR",
+];
+
+static LABEL_TAGS: &'static [&'static str] = &[
+  "Label",
+  "Publisher",
+];
+
+static CATALOG_NUMBER_TAGS: &'static [&'static str] = &[
+  "CATALOGNUMBER",
+  "CatalogNumber",
+];
+
+// Parameter of the first audio stream, `None` if it is empty
+fn read_audio_param(media_info: &mut MediaInfo, name: &str) -> Option<String> {
+  media_info.get(name, MediaInfoInfo::Text, MediaInfoStream::Audio).ok()
+    .map(|value| value.trim().to_owned())
+    .and_then(|value| if value.is_empty() { None } else { Some(value) })
+}
+
+// First of the named tags with a value
+fn read_tag(media_info: &mut MediaInfo, names: &[&str]) -> Option<String> {
+  names.iter()
+    .filter_map(|name| media_info.get_with_default_options(name).ok())
+    .map(|value| value.trim().to_owned())
+    .find(|value| !value.is_empty())
+}
+
+// First of the named tags holding a valid UUID
+fn read_uuid_tag(media_info: &mut MediaInfo, names: &[&str]) -> Option<Uuid> {
+  names.iter()
+    .filter_map(|name| media_info.get_with_default_options(name).ok())
+    .filter_map(|value| Uuid::parse_str(value.trim()).ok())
+    .next()
+}
+
+pub struct MediaInfoReader;
+
+impl MetadataReader for MediaInfoReader {
+  fn name(&self) -> &'static str {
+    "mediainfo"
+  }
+
+  fn read(&self, path: &str, mtime: DateTime<Utc>) -> Result<ReadResult, ProcessorError> {
+    let mut media_info: MediaInfo = MediaInfo::new();
+
+    // Fail quickly if the file could not be opened
+    if media_info.open(path).is_err() {
+      return Err(ProcessorError::Unreadable(self.name()));
+    }
+
+    // Filter out any file without an audio stream
+    //
+    // `get_with_default_option` throws a ZeroLengthError if there is no value
+    // for the parameter
+    let audio_streams = media_info.get_with_default_options("AudioCount");
+    if audio_streams.is_err() {
+      trace!("no audio streams");
+      return Ok(ReadResult::Declined);
+    }
+
+    // Filter out any file with no duration
+    let duration = media_info.get_duration_ms().unwrap_or(0);
+    if duration == 0 {
+      trace!("duration == 0");
+      return Ok(ReadResult::Declined);
+    }
+
+    // Filter out playlist and backup files whatever their extension
+    let format_extension = media_info.get_with_default_options("Format/Extensions");
+    let ignore = match format_extension {
+      Ok(ref format_extension) => match format_extension.as_ref() {
+        "m3u8" |
+        "mpls" |
+        "orig" |
+        "bak" => true,
+
+        _ => false,
+      },
+      Err(_) => false,
+    };
+    if ignore {
+      trace!("ignoring {:?} extension", format_extension);
+      return Ok(ReadResult::Excluded);
+    }
+
+    // Set the title to the file name (minus extension) if there is no
+    // title set in the file's metadata
+    let title = match media_info.get_title() {
+      Ok(v) => Some(v),
+      Err(_) => media_info.get_with_default_options("FileName").ok()
+    };
+
+    // Uncompressed PCM does not report a compression mode
+    let codec = read_audio_param(&mut media_info, "Format");
+    let lossless = read_audio_param(&mut media_info, "Compression_Mode")
+      .map(|mode| mode == "Lossless")
+      .or_else(|| codec.as_ref().map(|codec| codec == "PCM"));
+
+    // Overall bitrate for containers that do not report the stream's
+    let bitrate = read_audio_param(&mut media_info, "BitRate")
+      .or_else(|| read_tag(&mut media_info, &["OverallBitRate"]))
+      .and_then(|v| parse_number(&v));
+
+    let file_info = NewMediaFileInfo {
+      title:        title,
+      artist:       media_info.get_performer().ok(),
+      album:        media_info.get_album().ok(),
+      track:        media_info.get_track_name().ok(),
+      track_number: media_info.get_track_number().unwrap_or(0),
+      duration:     duration,
+
+      tagged_mbid:             read_uuid_tag(&mut media_info, RECORDING_ID_TAGS),
+      tagged_release_id:       read_uuid_tag(&mut media_info, RELEASE_ID_TAGS),
+      tagged_release_group_id: read_uuid_tag(&mut media_info, RELEASE_GROUP_ID_TAGS),
+      isrc:                    read_tag(&mut media_info, ISRC_TAGS),
+
+      album_artist:   media_info.get_with_default_options("Album/Performer").ok(),
+      disc_number:    read_tag(&mut media_info, &["Part/Position"]).and_then(|v| parse_number(&v)),
+      disc_total:     read_tag(&mut media_info, &["Part/Position_Total"]).and_then(|v| parse_number(&v)),
+      track_total:    read_tag(&mut media_info, &["Track/Position_Total"]).and_then(|v| parse_number(&v)),
+      date:           read_tag(&mut media_info, DATE_TAGS),
+      original_date:  read_tag(&mut media_info, ORIGINAL_DATE_TAGS),
+      genres:         read_tag(&mut media_info, &["Genre"]).map(|v| split_genres(&v)).unwrap_or_default(),
+      composer:       read_tag(&mut media_info, &["Composer"]),
+      label:          read_tag(&mut media_info, LABEL_TAGS),
+      catalog_number: read_tag(&mut media_info, CATALOG_NUMBER_TAGS),
+      comment:        read_tag(&mut media_info, &["Comment"]),
+
+      container_format: media_info.get_with_default_options("Format").ok(),
+      codec:            codec,
+      codec_profile:    read_audio_param(&mut media_info, "Format_Profile"),
+      lossless:         lossless,
+      bitrate:          bitrate,
+      vbr:              read_audio_param(&mut media_info, "BitRate_Mode").map(|mode| mode == "VBR"),
+      sample_rate:      read_audio_param(&mut media_info, "SamplingRate").and_then(|v| parse_number(&v)),
+      bit_depth:        read_audio_param(&mut media_info, "BitDepth").and_then(|v| parse_number(&v)),
+      channels:         read_audio_param(&mut media_info, "Channel(s)").and_then(|v| parse_number(&v)),
+      channel_layout:   read_audio_param(&mut media_info, "ChannelLayout"),
+      encoder:          read_audio_param(&mut media_info, "Encoded_Library")
+                          .or_else(|| read_tag(&mut media_info, &["Encoded_Library"])),
+      encoder_settings: read_audio_param(&mut media_info, "Encoded_Library_Settings"),
+
+      ..NewMediaFileInfo::new(path, mtime)
+    };
+
+    media_info.close();
+
+    Ok(ReadResult::Read(file_info))
+  }
+}
+
+// Tags of a file as ffmpeg reports them, with lowercase names
+//
+// Tags of the container take precedence over tags of the audio stream, Ogg
+// files only have the latter.
+struct FfmpegTags(HashMap<String, String>);
+
+impl FfmpegTags {
+  fn new(container: &DictionaryRef, stream: &DictionaryRef) -> Self {
+    let mut tags = HashMap::new();
+
+    for dictionary in &[stream, container] {
+      for (name, value) in dictionary.iter() {
+        let value = value.trim();
+        if !value.is_empty() {
+          tags.insert(name.to_lowercase(), value.to_owned());
+        }
+      }
+    }
+
+    FfmpegTags(tags)
+  }
+
+  // First of the named tags with a value
+  fn get(&self, names: &[&str]) -> Option<String> {
+    names.iter()
+      .filter_map(|name| self.0.get(*name))
+      .next()
+      .cloned()
+  }
+
+  fn get_uuid(&self, names: &[&str]) -> Option<Uuid> {
+    names.iter()
+      .filter_map(|name| self.0.get(*name))
+      .filter_map(|value| Uuid::parse_str(value).ok())
+      .next()
+  }
+}
+
+// Codec name as MediaInfo reports it for the codecs it has a different name
+// for than ffmpeg, so the same codec is stored under one name whichever
+// reader read the file, and whether the codec is lossless
+fn describe_codec(id: &str) -> (String, bool) {
+  match id {
+    "MP3"         => ("MPEG Audio".to_owned(), false),
+    "VORBIS"      => ("Vorbis".to_owned(), false),
+    "OPUS"        => ("Opus".to_owned(), false),
+    "APE"         => ("Monkey's Audio".to_owned(), true),
+    "WAVPACK"     => ("WavPack".to_owned(), true),
+    "FLAC" |
+    "ALAC" |
+    "TTA" |
+    "TAK" |
+    "MLP" |
+    "TRUEHD"      => (id.to_owned(), true),
+    "WMALOSSLESS" => ("WMA".to_owned(), true),
+    id if id.starts_with("PCM_") => ("PCM".to_owned(), true),
+    id if id.starts_with("DSD_") => ("DSD".to_owned(), true),
+    id => (id.to_owned(), false),
+  }
+}
+
+// Bits per sample of the source as the demuxer read them, which the bindings
+// do not expose. The sample format of the decoder is no help, 24 bit FLAC is
+// decoded to 32 bit samples for example.
+fn bits_per_sample(parameters: &codec::Parameters) -> Option<i32> {
+  // SAFETY: `as_ptr` points at the `AVCodecParameters` the bindings' value
+  // wraps, allocated with the stream and never null, which lives at least as
+  // long as the `parameters` borrow. Only two plain integer fields are read
+  // and nothing is written.
+  let (raw, coded) = unsafe {
+    let parameters = parameters.as_ptr();
+    ((*parameters).bits_per_raw_sample, (*parameters).bits_per_coded_sample)
+  };
+
+  if raw > 0 {
+    Some(raw)
+  } else if coded > 0 {
+    Some(coded)
+  } else {
+    None
+  }
+}
+
+pub struct FfmpegReader;
+
+impl MetadataReader for FfmpegReader {
+  fn name(&self) -> &'static str {
+    "ffmpeg"
+  }
+
+  fn read(&self, path: &str, mtime: DateTime<Utc>) -> Result<ReadResult, ProcessorError> {
+    let ictx = try!(format::input(&path));
+
+    let stream = match ictx.streams().best(Type::Audio) {
+      Some(v) => v,
+      None => {
+        trace!("no audio streams");
+        return Ok(ReadResult::Declined);
+      },
+    };
+
+    // Container duration is in microseconds, fall back on the stream's for
+    // containers that do not report one
+    let duration = if ictx.duration() > 0 {
+      ictx.duration() / 1000
+    } else {
+      (stream.duration() as f64 * f64::from(stream.time_base()) * 1000.0) as i64
+    };
+    if duration <= 0 {
+      trace!("duration == 0");
+      return Ok(ReadResult::Declined);
+    }
+
+    let (codec, lossless) = describe_codec(&format!("{:?}", stream.codec().id()));
+
+    let mut decoder = try!(stream.codec().decoder().audio());
+    try!(decoder.set_parameters(stream.parameters()));
+
+    // Only the sample size of lossless codecs says anything about the source
+    //
+    // DSD is decoded to floats eight bits at a time, so its rate is reported
+    // as MediaInfo does, in single bit samples per second.
+    let dsd = codec == "DSD";
+    let bit_depth = if dsd {
+      Some(1)
+    } else if lossless {
+      bits_per_sample(&stream.parameters())
+    } else {
+      None
+    };
+    let sample_rate = if dsd {
+      decoder.rate() * 8
+    } else {
+      decoder.rate()
+    };
+
+    let bitrate = if decoder.bit_rate() > 0 {
+      Some(decoder.bit_rate() as i32)
+    } else if ictx.bit_rate() > 0 {
+      Some(ictx.bit_rate() as i32)
+    } else {
+      None
+    };
+
+    let tags = FfmpegTags::new(&ictx.metadata(), &stream.metadata());
+    let track = tags.get(&["track", "tracknumber"]);
+    let disc = tags.get(&["disc", "discnumber"]);
+
+    // Set the title to the file name (minus extension) if there is no
+    // title set in the file's metadata
+    let title = tags.get(&["title"]).or_else(|| {
+      Path::new(path).file_stem().and_then(|stem| stem.to_str()).map(|stem| stem.to_owned())
+    });
+
+    let file_info = NewMediaFileInfo {
+      title:        title,
+      artist:       tags.get(&["artist"]),
+      album:        tags.get(&["album"]),
+      track:        tags.get(&["title"]),
+      track_number: track.as_ref().and_then(|v| parse_number(v)).unwrap_or(0) as u32,
+      duration:     duration as u32,
+
+      tagged_mbid:             tags.get_uuid(&["musicbrainz_trackid", "musicbrainz track id"]),
+      tagged_release_id:       tags.get_uuid(&["musicbrainz_albumid", "musicbrainz album id"]),
+      tagged_release_group_id: tags.get_uuid(&["musicbrainz_releasegroupid", "musicbrainz release group id"]),
+      isrc:                    tags.get(&["isrc", "tsrc"]),
+
+      album_artist:   tags.get(&["album_artist", "albumartist", "album artist"]),
+      disc_number:    disc.as_ref().and_then(|v| parse_number(v)),
+      disc_total:     tags.get(&["disctotal", "totaldiscs"]).and_then(|v| parse_number(&v))
+                        .or_else(|| disc.as_ref().and_then(|v| parse_total(v))),
+      track_total:    tags.get(&["tracktotal", "totaltracks"]).and_then(|v| parse_number(&v))
+                        .or_else(|| track.as_ref().and_then(|v| parse_total(v))),
+      date:           tags.get(&["date", "year"]),
+      original_date:  tags.get(&["originaldate", "tdor", "originalyear"]),
+      genres:         tags.get(&["genre"]).map(|v| split_genres(&v)).unwrap_or_default(),
+      composer:       tags.get(&["composer"]),
+      label:          tags.get(&["label", "publisher", "organization"]),
+      catalog_number: tags.get(&["catalognumber"]),
+      comment:        tags.get(&["comment", "description"]),
+
+      container_format: Some(ictx.format().name().to_owned()),
+      codec:            Some(codec),
+      codec_profile:    None,
+      lossless:         Some(lossless),
+      bitrate:          bitrate,
+      vbr:              None,
+      sample_rate:      Some(sample_rate as i32),
+      bit_depth:        bit_depth,
+      channels:         Some(i32::from(decoder.channels())),
+      channel_layout:   None,
+      encoder:          tags.get(&["encoder"]),
+      encoder_settings: None,
+
+      ..NewMediaFileInfo::new(path, mtime)
+    };
+
+    Ok(ReadResult::Read(file_info))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use test_audio::{self, Recording, DURATION, FORMATS};
+
+  fn readers() -> Vec<Box<MetadataReader>> {
+    vec![Box::new(MediaInfoReader), Box::new(FfmpegReader)]
+  }
+
+  #[test]
+  fn test_parse_number() {
+    assert_eq!(parse_number("3"), Some(3));
+    assert_eq!(parse_number(" 3/12"), Some(3));
+    assert_eq!(parse_number("2011-08-13"), Some(2011));
+    assert_eq!(parse_number("/12"), None);
+
+    assert_eq!(parse_total("3/12"), Some(12));
+    assert_eq!(parse_total("3"), None);
+  }
+
+  #[test]
+  fn test_split_genres() {
+    assert_eq!(split_genres("Jazz / Touhou"), vec!["Jazz", "Touhou"]);
+    assert_eq!(split_genres("Jazz;Touhou; "), vec!["Jazz", "Touhou"]);
+    assert!(split_genres(" ").is_empty());
+  }
+
+  #[test]
+  fn test_describe_codec() {
+    assert_eq!(describe_codec("MP3"), ("MPEG Audio".to_owned(), false));
+    assert_eq!(describe_codec("FLAC"), ("FLAC".to_owned(), true));
+    assert_eq!(describe_codec("PCM_S24LE"), ("PCM".to_owned(), true));
+    assert_eq!(describe_codec("AAC"), ("AAC".to_owned(), false));
+  }
+
+  #[test]
+  fn test_read_formats() {
+    ::ffmpeg::init().unwrap();
+
+    for &format in FORMATS {
+      let recording = Recording::write(format, "metadata");
+
+      for reader in readers() {
+        let context = format!("{} reading {:?}", reader.name(), format);
+        let info = reader.read(recording.path(), Utc::now())
+          .unwrap_or_else(|err| panic!("{}: {:#?}", context, err))
+          .info()
+          .unwrap_or_else(|| panic!("{}: not a music file", context));
+
+        let duration = i64::from(info.duration);
+        assert!((duration - i64::from(DURATION) * 1000).abs() <= 100, "{}: duration {}", context, duration);
+        assert_eq!(info.sample_rate, Some(format.sample_rate() as i32), "{}", context);
+        assert_eq!(info.channels, Some(1), "{}", context);
+        assert_eq!(info.codec.as_ref().map(|codec| codec.as_str()), Some(format.codec()), "{}", context);
+
+        if reader.name() == "ffmpeg" && format.has_title() {
+          assert_eq!(info.title.as_ref().map(|title| title.as_str()), Some(test_audio::TITLE), "{}", context);
+        }
+      }
+
+      if format == test_audio::Format::Bwf {
+        let info = FfmpegReader.read(recording.path(), Utc::now()).unwrap().info().unwrap();
+        assert_eq!(info.comment.as_ref().map(|comment| comment.as_str()), Some(test_audio::DESCRIPTION));
+      }
+    }
+  }
+
+  // Monkey's Audio, WavPack and TTA files are read from `tests/fixtures/audio`
+  #[test]
+  fn test_read_encoded_formats() {
+    ::ffmpeg::init().unwrap();
+
+    for extension in test_audio::ENCODED_EXTENSIONS {
+      let path = test_audio::encoded_fixture(extension);
+
+      for reader in readers() {
+        let context = format!("{} reading {}", reader.name(), extension);
+        let info = reader.read(&path, Utc::now())
+          .unwrap_or_else(|err| panic!("{}: {:#?}", context, err))
+          .info()
+          .unwrap_or_else(|| panic!("{}: not a music file", context));
+
+        let duration = i64::from(info.duration);
+        assert!((duration - i64::from(DURATION) * 1000).abs() <= 100, "{}: duration {}", context, duration);
+        assert_eq!(info.lossless, Some(true), "{}", context);
+        assert_eq!(info.bit_depth, Some(16), "{}", context);
+      }
+    }
+  }
+}
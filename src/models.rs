@@ -1,12 +1,29 @@
 use std::fs;
 use std::time::UNIX_EPOCH;
 
+use chromaprint::Chromaprint;
 use chrono::{DateTime, TimeZone, Utc};
 use diesel::sql_types::Integer;
-use mediainfo::MediaInfo;
+use serde_json;
 use uuid::Uuid;
 
-use schema::{acoustid_last_checks, library};
+use basic_types::{AcoustIdRecording, AcoustIdRelease, AcoustIdResult, ProcessorError, ScanPhase};
+use fingerprint;
+use matcher::{self, ReleaseChoice};
+use metadata::{parse_number, METADATA_VERSION};
+use schema::{acoustid_candidates, acoustid_last_checks, acoustid_responses, acoustid_submissions, fingerprints, library, scan_failures};
+
+// 64-bit FNV-1a hash for values stored in the database, the standard library
+// hasher is not guaranteed to give the same result across Rust releases
+pub fn stable_hash(data: &[u8]) -> i64 {
+  let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+  for &byte in data {
+    hash ^= u64::from(byte);
+    hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+  }
+
+  hash as i64
+}
 
 #[derive(Clone, Debug, Insertable, AsChangeset)]
 #[table_name="library"]
@@ -20,6 +37,50 @@ pub struct NewMediaFileInfo {
   pub track: Option<String>,
   pub track_number: u32,
   pub duration: u32,
+
+  pub file_size: Option<i64>,
+
+  // Identifiers from the file's tags, as written by Picard for example
+  pub tagged_mbid: Option<Uuid>,
+  pub tagged_release_id: Option<Uuid>,
+  pub tagged_release_group_id: Option<Uuid>,
+  pub isrc: Option<String>,
+
+  pub album_artist: Option<String>,
+  pub disc_number: Option<i32>,
+  pub disc_total: Option<i32>,
+  pub track_total: Option<i32>,
+
+  // As written in the tags, usually as much of `YYYY-MM-DD` as is known
+  pub date: Option<String>,
+  pub original_date: Option<String>,
+
+  pub genres: Vec<String>,
+  pub composer: Option<String>,
+  pub label: Option<String>,
+  pub catalog_number: Option<String>,
+  pub comment: Option<String>,
+
+  // Properties of the audio stream as MediaInfo reports them, the bitrate in
+  // bits per second
+  pub container_format: Option<String>,
+  pub codec: Option<String>,
+  pub codec_profile: Option<String>,
+  pub lossless: Option<bool>,
+  pub bitrate: Option<i32>,
+  pub vbr: Option<bool>,
+  pub sample_rate: Option<i32>,
+  pub bit_depth: Option<i32>,
+  pub channels: Option<i32>,
+  pub channel_layout: Option<String>,
+
+  // Library that encoded the audio and the settings it was given, LAME's
+  // for example
+  pub encoder: Option<String>,
+  pub encoder_settings: Option<String>,
+
+  // `METADATA_VERSION` of the reader that read the file
+  pub metadata_version: i32,
 }
 
 #[derive(Clone, Debug, Queryable, Identifiable)]
@@ -39,6 +100,79 @@ pub struct MediaFileInfo {
   pub mbid: Option<Uuid>,
 
   pub mtime: DateTime<Utc>,
+
+  // Hash of the document last sent to Elasticsearch for this entry
+  pub indexed_hash: Option<i64>,
+
+  pub file_size: Option<i64>,
+
+  // Release the recording in `mbid` was matched on
+  pub release_id: Option<Uuid>,
+  pub release_group_id: Option<Uuid>,
+  pub medium_position: Option<i32>,
+  pub track_position: Option<i32>,
+  pub release_date: Option<String>,
+
+  pub tagged_mbid: Option<Uuid>,
+  pub tagged_release_id: Option<Uuid>,
+  pub tagged_release_group_id: Option<Uuid>,
+  pub isrc: Option<String>,
+
+  // Recording AcoustID matched the file's fingerprint to, kept apart from
+  // `mbid` so it can be compared with `tagged_mbid`
+  pub fingerprint_mbid: Option<Uuid>,
+
+  pub album_artist: Option<String>,
+  pub disc_number: Option<i32>,
+  pub disc_total: Option<i32>,
+  pub track_total: Option<i32>,
+  pub date: Option<String>,
+  pub original_date: Option<String>,
+  pub genres: Vec<String>,
+  pub composer: Option<String>,
+  pub label: Option<String>,
+  pub catalog_number: Option<String>,
+  pub comment: Option<String>,
+
+  pub container_format: Option<String>,
+  pub codec: Option<String>,
+  pub codec_profile: Option<String>,
+  pub lossless: Option<bool>,
+  pub bitrate: Option<i32>,
+  pub vbr: Option<bool>,
+  pub sample_rate: Option<i32>,
+  pub bit_depth: Option<i32>,
+  pub channels: Option<i32>,
+  pub channel_layout: Option<String>,
+  pub encoder: Option<String>,
+  pub encoder_settings: Option<String>,
+
+  pub metadata_version: i32,
+}
+
+// Release of a matched recording a file most likely came from
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReleaseInfo {
+  pub release_id: Option<Uuid>,
+  pub release_group_id: Option<Uuid>,
+  pub medium_position: Option<i32>,
+  pub track_position: Option<i32>,
+
+  // As much of `YYYY-MM-DD` as is known
+  pub release_date: Option<String>,
+}
+
+// State of a library entry loaded ahead of a scan, enough to tell whether the
+// file needs any work without querying the database per file
+#[derive(Clone, Debug)]
+pub struct ExistingFile {
+  pub id: i32,
+  pub mtime: DateTime<Utc>,
+  pub mbid: Option<Uuid>,
+  pub tagged_mbid: Option<Uuid>,
+  pub indexed_hash: Option<i64>,
+  pub last_check: Option<DateTime<Utc>>,
+  pub metadata_version: i32,
 }
 
 #[derive(Queryable, Identifiable, Associations)]
@@ -50,6 +184,153 @@ pub struct AcoustIdLastCheck {
   pub last_check: DateTime<Utc>,
 }
 
+// A recording AcoustID matched a library entry's fingerprint to
+#[derive(Clone, Debug, Queryable)]
+pub struct AcoustIdCandidate {
+  pub id: i32,
+  pub library_id: i32,
+  pub acoustid_id: String,
+  pub score: f32,
+  pub recording_id: Uuid,
+  pub title: Option<String>,
+  pub artists: Option<String>,
+  pub duration: Option<i32>,
+  pub release_id: Option<Uuid>,
+  pub release_group_id: Option<Uuid>,
+  pub medium_position: Option<i32>,
+  pub track_position: Option<i32>,
+  pub release_date: Option<String>,
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name="acoustid_candidates"]
+pub struct NewAcoustIdCandidate {
+  pub library_id: i32,
+  pub acoustid_id: String,
+  pub score: f32,
+  pub recording_id: Uuid,
+  pub title: Option<String>,
+  pub artists: Option<String>,
+
+  // Seconds
+  pub duration: Option<i32>,
+
+  // Release of the recording picked for the entry
+  pub release_id: Option<Uuid>,
+  pub release_group_id: Option<Uuid>,
+  pub medium_position: Option<i32>,
+  pub track_position: Option<i32>,
+  pub release_date: Option<String>,
+}
+
+// State of the review of an entry without a clear AcoustID match
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReviewStatus {
+  Pending,
+  Accepted,
+  Rejected,
+  Manual,
+}
+
+impl ReviewStatus {
+  pub fn as_str(&self) -> &'static str {
+    match *self {
+      ReviewStatus::Pending  => "pending",
+      ReviewStatus::Accepted => "accepted",
+      ReviewStatus::Rejected => "rejected",
+      ReviewStatus::Manual   => "manual",
+    }
+  }
+}
+
+// Review of an entry's AcoustID candidates. Once decided the entry is not
+// looked up on AcoustID again, so the decision survives rescans.
+#[derive(Clone, Debug, Queryable)]
+pub struct AcoustIdReview {
+  pub library_id: i32,
+  pub status: String,
+  pub mbid: Option<Uuid>,
+  pub created_at: DateTime<Utc>,
+  pub decided_at: Option<DateTime<Utc>>,
+}
+
+impl AcoustIdReview {
+  pub fn is_pending(&self) -> bool {
+    self.status == ReviewStatus::Pending.as_str()
+  }
+}
+
+// Fingerprint of a library entry submitted to AcoustID with the MusicBrainz
+// ID from the file's tags. Each entry is only ever submitted once.
+#[derive(Clone, Debug, Queryable)]
+pub struct AcoustIdSubmissionRecord {
+  pub library_id: i32,
+  pub submission_id: i64,
+  pub mbid: Uuid,
+  pub status: String,
+
+  // AcoustID track the fingerprint was added to once imported
+  pub acoustid_id: Option<String>,
+
+  pub submitted_at: DateTime<Utc>,
+  pub checked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name="acoustid_submissions"]
+pub struct NewAcoustIdSubmission {
+  pub library_id: i32,
+  pub submission_id: i64,
+  pub mbid: Uuid,
+  pub status: String,
+}
+
+// Chromaprint fingerprint of a library entry's file, kept so the file does
+// not have to be decoded again for every AcoustID lookup
+//
+// `mtime` is the modification time of the file when it was fingerprinted,
+// the fingerprint is only reused while it matches.
+#[derive(Clone, Debug, Queryable, Insertable, AsChangeset)]
+#[table_name="fingerprints"]
+pub struct Fingerprint {
+  pub library_id: i32,
+  pub fingerprint: String,
+  pub raw: Vec<i32>,
+  pub duration: f64,
+  pub algorithm: i32,
+  pub version: String,
+  pub mtime: DateTime<Utc>,
+}
+
+// Results of an AcoustID lookup, keyed by a hash of the request
+#[derive(Clone, Debug, Queryable, Insertable, AsChangeset)]
+#[table_name="acoustid_responses"]
+pub struct CachedResponse {
+  pub key: i64,
+  pub results: serde_json::Value,
+  pub fetched_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Queryable)]
+pub struct ScanFailure {
+  pub id: i32,
+  pub path: String,
+  pub phase: String,
+  pub kind: String,
+  pub message: String,
+  pub failed_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Insertable, AsChangeset)]
+#[table_name="scan_failures"]
+pub struct NewScanFailure {
+  pub path: String,
+  pub phase: String,
+  pub kind: String,
+  pub message: String,
+  pub failed_at: DateTime<Utc>,
+}
+
 #[derive(Debug, QueryableByName)]
 pub struct MusicBrainzRecording {
   #[sql_type = "Integer"]
@@ -68,13 +349,53 @@ pub struct MediaFileInfoDocument {
   pub track: Option<String>,
   pub track_number: i32,
   pub duration: i32,
+  pub file_size: Option<i64>,
 
   pub mbid: Option<String>,
+
+  pub release_id: Option<String>,
+  pub release_group_id: Option<String>,
+  pub medium_position: Option<i32>,
+  pub track_position: Option<i32>,
+  pub release_date: Option<String>,
+
+  pub tagged_mbid: Option<String>,
+  pub fingerprint_mbid: Option<String>,
+  pub isrc: Option<String>,
+
+  pub album_artist: Option<String>,
+  pub disc_number: Option<i32>,
+  pub disc_total: Option<i32>,
+  pub track_total: Option<i32>,
+  pub date: Option<String>,
+  pub original_date: Option<String>,
+
+  // Year of `date`, for range queries
+  pub year: Option<i32>,
+
+  pub genres: Vec<String>,
+  pub composer: Option<String>,
+  pub label: Option<String>,
+  pub catalog_number: Option<String>,
+  pub comment: Option<String>,
+
+  pub container_format: Option<String>,
+  pub codec: Option<String>,
+  pub codec_profile: Option<String>,
+  pub lossless: Option<bool>,
+  pub bitrate: Option<i32>,
+  pub vbr: Option<bool>,
+  pub sample_rate: Option<i32>,
+  pub bit_depth: Option<i32>,
+  pub channels: Option<i32>,
+  pub channel_layout: Option<String>,
+  pub encoder: Option<String>,
+  pub encoder_settings: Option<String>,
 }
 
 impl NewMediaFileInfo {
   // Get file modification time
-  pub fn get_mtime(path: &str) -> DateTime<Utc> {
+  pub fn get_mtime(path: &str) -> Option<DateTime<Utc>> {
     fs::metadata(path).ok().and_then(|meta| {
       meta.modified().ok()
     }).and_then(|time| {
@@ -83,110 +404,289 @@ impl NewMediaFileInfo {
       // Reduce precision of nanoseconds to microseconds because PostgreSQL
       // does not store the same amount of precision
       Utc.timestamp(duration.as_secs() as i64, (duration.subsec_nanos() / 1000) * 1000)
-    }).expect(&format!("Unable to get modification time for path: {}", path))
+    })
   }
 
-  pub fn read_file(path: &str) -> Option<Self> {
-    let mut media_info: MediaInfo = MediaInfo::new();
+  // Entry for a file with only its path, modification time and size known,
+  // for a metadata reader to fill in
+  pub fn new(path: &str, mtime: DateTime<Utc>) -> Self {
+    NewMediaFileInfo {
+      path:         path.to_owned(),
+      mtime:        mtime,
+      file_size:    fs::metadata(path).ok().map(|meta| meta.len() as i64),
+
+      title:        None,
+      artist:       None,
+      album:        None,
+      track:        None,
+      track_number: 0,
+      duration:     0,
+
+      tagged_mbid:             None,
+      tagged_release_id:       None,
+      tagged_release_group_id: None,
+      isrc:                    None,
+
+      album_artist:   None,
+      disc_number:    None,
+      disc_total:     None,
+      track_total:    None,
+      date:           None,
+      original_date:  None,
+      genres:         Vec::new(),
+      composer:       None,
+      label:          None,
+      catalog_number: None,
+      comment:        None,
+
+      container_format: None,
+      codec:            None,
+      codec_profile:    None,
+      lossless:         None,
+      bitrate:          None,
+      vbr:              None,
+      sample_rate:      None,
+      bit_depth:        None,
+      channels:         None,
+      channel_layout:   None,
+      encoder:          None,
+      encoder_settings: None,
+
+      metadata_version: METADATA_VERSION,
+    }
+  }
+
+  #[inline]
+  pub fn is_default_values(&self) -> bool {
+    self.title  == None &&
+    self.artist == None &&
+    self.album  == None &&
+    self.track  == None &&
+    self.track_number == 0 &&
+    self.duration == 0
+  }
+}
 
-    // Fail quickly if the file could not be opened
-    if media_info.open(path).is_err() {
-      error!("could not open file: {}", path);
-      return None;
+impl ReleaseInfo {
+  fn from_release(release: &AcoustIdRelease, release_group_id: Option<Uuid>) -> Self {
+    // Only the medium with the recording's track lists any tracks
+    let medium = release.mediums.as_ref().and_then(|mediums| {
+      mediums.iter().find(|medium| medium.tracks.as_ref().map(|tracks| !tracks.is_empty()).unwrap_or(false))
+    });
+    let track = medium
+      .and_then(|medium| medium.tracks.as_ref())
+      .and_then(|tracks| tracks.first());
+
+    ReleaseInfo {
+      release_id:       Some(release.id),
+      release_group_id: release_group_id,
+      medium_position:  medium.and_then(|medium| medium.position),
+      track_position:   track.and_then(|track| track.position),
+      release_date:     release.date.as_ref().and_then(|date| date.format()),
     }
+  }
+}
 
-    // Filter out any file without an audio stream
-    //
-    // `get_with_default_option` throws a ZeroLengthError if there is no value
-    // for the parameter
-    let audio_streams = media_info.get_with_default_options("AudioCount");
-    if audio_streams.is_err() {
-      trace!("no audio streams");
-      return None;
+// Every release a recording appears on, whether listed directly or under
+// its release groups
+fn release_choices(recording: &AcoustIdRecording) -> Vec<ReleaseChoice> {
+  let mut choices = Vec::new();
+
+  if let Some(ref groups) = recording.releasegroups {
+    for group in groups {
+      for release in group.releases.iter().flat_map(|releases| releases) {
+        choices.push(ReleaseChoice {
+          title:   release.title.clone().or_else(|| group.title.clone()),
+          release: ReleaseInfo::from_release(release, Some(group.id)),
+        });
+      }
     }
+  }
 
-    // Filter out any file with no duration
-    let duration = media_info.get_duration_ms().unwrap_or(0);
-    if duration == 0 {
-      trace!("duration == 0");
-      return None;
+  if let Some(ref releases) = recording.releases {
+    for release in releases {
+      choices.push(ReleaseChoice {
+        title:   release.title.clone(),
+        release: ReleaseInfo::from_release(release, None),
+      });
     }
+  }
 
-    // Filter out m3u8 and mpls files, they have a duration according to
-    // mediainfo, but I do not want playlist files in the database. 
-    // I do not want my backup files to be included (orig and bak files).
-    // APE files break decoding and I do not know why and I do not use them.
-    let format_extension = media_info.get_with_default_options("Format/Extensions");
-    let extension = media_info.get_with_default_options("FileExtension");
-    let ignore = match format_extension {
-      Ok(ref format_extension) => match format_extension.as_ref() {
-        "ape mac" |
-        "m3u8" |
-        "mpls" |
-        "orig" |
-        "bak" => true,
-
-        _ => false,
-      },
-      Err(_) => false,
-    } || match extension {
-      Ok(ref extension) => match extension.as_ref() {
-        "orig" |
-        "bak" => true,
-
-        _ => false,
-      },
-      Err(_) => false,
-    };
-    if ignore {
-      trace!("ignoring {:?} or {:?} extension", format_extension, extension);
-      return None;
+  choices
+}
+
+impl NewAcoustIdCandidate {
+  // One candidate per recording of each result, along with the release of
+  // the recording that best fits the entry
+  pub fn from_results(info: &MediaFileInfo, results: &[AcoustIdResult]) -> Vec<Self> {
+    let mut candidates: Vec<Self> = Vec::new();
+
+    for result in results {
+      let recordings = match result.recordings {
+        Some(ref v) => v,
+        None => continue,
+      };
+
+      for recording in recordings {
+        // Recordings can be listed more than once in a result
+        if candidates.iter().any(|c| c.acoustid_id == result.id && c.recording_id == recording.id) {
+          continue;
+        }
+
+        let artists = recording.artists.as_ref().map(|artists| {
+          artists.iter()
+            .map(|artist| artist.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+        });
+
+        let release = matcher::best_release(info, release_choices(recording)).unwrap_or_default();
+
+        candidates.push(NewAcoustIdCandidate {
+          library_id:       info.id,
+          acoustid_id:      result.id.clone(),
+          score:            result.score,
+          recording_id:     recording.id,
+          title:            recording.title.clone(),
+          artists:          artists,
+          duration:         recording.duration,
+          release_id:       release.release_id,
+          release_group_id: release.release_group_id,
+          medium_position:  release.medium_position,
+          track_position:   release.track_position,
+          release_date:     release.release_date,
+        });
+      }
     }
 
-    // Set the title to the file name (minus extension) if there is no
-    // title set in the file's metadata
-    let title = match media_info.get_title() {
-      Ok(v) => Some(v),
-      Err(_) => media_info.get_with_default_options("FileName").ok()
-    };
+    candidates
+  }
 
-    // Get file modification time
-    let mtime = Self::get_mtime(path);
+  pub fn release(&self) -> ReleaseInfo {
+    ReleaseInfo {
+      release_id:       self.release_id,
+      release_group_id: self.release_group_id,
+      medium_position:  self.medium_position,
+      track_position:   self.track_position,
+      release_date:     self.release_date.clone(),
+    }
+  }
+}
 
-    // Store the most relevant details in a struct for easy access
-    let file_info = NewMediaFileInfo {
-      path:         path.to_owned(),
-      mtime:        mtime,
+impl AcoustIdCandidate {
+  pub fn release(&self) -> ReleaseInfo {
+    ReleaseInfo {
+      release_id:       self.release_id,
+      release_group_id: self.release_group_id,
+      medium_position:  self.medium_position,
+      track_position:   self.track_position,
+      release_date:     self.release_date.clone(),
+    }
+  }
+}
 
-      title:        title,
-      artist:       media_info.get_performer().ok(),
-      album:        media_info.get_album().ok(),
-      track:        media_info.get_track_name().ok(),
-      track_number: media_info.get_track_number().unwrap_or(0),
-      duration:     duration,
-    };
+impl Fingerprint {
+  // `None` if the compressed fingerprint could not be decoded
+  pub fn new(library_id: i32, mtime: DateTime<Utc>, duration: f64, compressed: String) -> Option<Self> {
+    let (algorithm, raw) = fingerprint::decode(&compressed)?;
+
+    Some(Fingerprint {
+      library_id:  library_id,
+      fingerprint: compressed,
+      // PostgreSQL has no unsigned integers, the bits are stored as is
+      raw:         raw.into_iter().map(|x| x as i32).collect(),
+      duration:    duration,
+      algorithm:   algorithm,
+      version:     Chromaprint::version().to_string(),
+      mtime:       mtime,
+    })
+  }
 
-    media_info.close();
+  pub fn raw_values(&self) -> Vec<u32> {
+    self.raw.iter().map(|&x| x as u32).collect()
+  }
+}
 
-    if file_info.is_default_values() {
-      return None;
+impl NewScanFailure {
+  pub fn new(path: &str, phase: ScanPhase, kind: &str, message: String) -> Self {
+    NewScanFailure {
+      path:      path.to_owned(),
+      phase:     phase.as_str().to_owned(),
+      kind:      kind.to_owned(),
+      message:   message,
+      failed_at: Utc::now(),
     }
+  }
 
-    Some(file_info)
+  pub fn from_error(path: &str, err: &ProcessorError) -> Self {
+    NewScanFailure {
+      path:      path.to_owned(),
+      phase:     err.phase().map(|phase| phase.as_str()).unwrap_or("unknown").to_owned(),
+      kind:      err.kind().to_owned(),
+      message:   err.root().to_string(),
+      failed_at: Utc::now(),
+    }
   }
+}
 
-  #[inline]
-  fn is_default_values(&self) -> bool {
-    self.title  == None &&
-    self.artist == None &&
-    self.album  == None &&
-    self.track  == None &&
-    self.track_number == 0 &&
-    self.duration == 0
+impl MediaFileInfoDocument {
+  // Hash of the serialized document, compared against
+  // `MediaFileInfo::indexed_hash` to skip sending unchanged documents
+  pub fn content_hash(&self) -> i64 {
+    let data = serde_json::to_vec(self).expect("failed to serialize document");
+
+    stable_hash(&data)
   }
 }
 
 impl MediaFileInfo {
+  // Release the file is tagged with, the track number counting as the
+  // position on it
+  pub fn tagged_release(&self) -> ReleaseInfo {
+    ReleaseInfo {
+      release_id:       self.tagged_release_id,
+      release_group_id: self.tagged_release_group_id,
+      medium_position:  None,
+      track_position:   if self.track_number > 0 { Some(self.track_number as i32) } else { None },
+      release_date:     None,
+    }
+  }
+
+  // Whether the tagged and fingerprinted recordings are both known and
+  // differ
+  pub fn mbid_disagrees(&self) -> bool {
+    match (self.tagged_mbid, self.fingerprint_mbid) {
+      (Some(tagged), Some(fingerprint)) => tagged != fingerprint,
+      _ => false,
+    }
+  }
+
+  // Store a recording AcoustID matched the file's fingerprint to, which only
+  // becomes its MusicBrainz ID if the file is not tagged with one
+  pub fn set_fingerprint_recording(&mut self, mbid: Uuid, release: ReleaseInfo) {
+    self.fingerprint_mbid = Some(mbid);
+
+    if self.tagged_mbid.is_none() {
+      self.set_recording(mbid, release);
+    }
+  }
+
+  // Year the file's date starts with
+  pub fn year(&self) -> Option<i32> {
+    let year = self.date.as_ref().and_then(|date| parse_number(date))?;
+
+    if year >= 1000 { Some(year) } else { None }
+  }
+
+  pub fn set_recording(&mut self, mbid: Uuid, release: ReleaseInfo) {
+    self.mbid             = Some(mbid);
+    self.release_id       = release.release_id;
+    self.release_group_id = release.release_group_id;
+    self.medium_position  = release.medium_position;
+    self.track_position   = release.track_position;
+    self.release_date     = release.release_date;
+  }
+
   pub fn to_document(&self) -> MediaFileInfoDocument {
     MediaFileInfoDocument {
       id:           self.id,
@@ -197,7 +697,105 @@ impl MediaFileInfo {
       track:        self.track.clone(),
       track_number: self.track_number as i32,
       duration:     self.duration as i32,
-      mbid:         self.mbid.map(|x| x.to_string())
+      file_size:    self.file_size,
+      mbid:         self.mbid.map(|x| x.to_string()),
+
+      release_id:       self.release_id.map(|x| x.to_string()),
+      release_group_id: self.release_group_id.map(|x| x.to_string()),
+      medium_position:  self.medium_position,
+      track_position:   self.track_position,
+      release_date:     self.release_date.clone(),
+
+      tagged_mbid:      self.tagged_mbid.map(|x| x.to_string()),
+      fingerprint_mbid: self.fingerprint_mbid.map(|x| x.to_string()),
+      isrc:             self.isrc.clone(),
+
+      album_artist:   self.album_artist.clone(),
+      disc_number:    self.disc_number,
+      disc_total:     self.disc_total,
+      track_total:    self.track_total,
+      date:           self.date.clone(),
+      original_date:  self.original_date.clone(),
+      year:           self.year(),
+      genres:         self.genres.clone(),
+      composer:       self.composer.clone(),
+      label:          self.label.clone(),
+      catalog_number: self.catalog_number.clone(),
+      comment:        self.comment.clone(),
+
+      container_format: self.container_format.clone(),
+      codec:            self.codec.clone(),
+      codec_profile:    self.codec_profile.clone(),
+      lossless:         self.lossless,
+      bitrate:          self.bitrate,
+      vbr:              self.vbr,
+      sample_rate:      self.sample_rate,
+      bit_depth:        self.bit_depth,
+      channels:         self.channels,
+      channel_layout:   self.channel_layout.clone(),
+      encoder:          self.encoder.clone(),
+      encoder_settings: self.encoder_settings.clone(),
+    }
+  }
+}
+
+// Entry a file read as `info` would be stored as before being matched to a
+// recording, for tests without a database
+#[cfg(test)]
+impl MediaFileInfo {
+  pub fn test_info(id: i32, info: NewMediaFileInfo) -> Self {
+    MediaFileInfo {
+      id:           id,
+      path:         info.path,
+      title:        info.title,
+      artist:       info.artist,
+      album:        info.album,
+      track:        info.track,
+      track_number: info.track_number,
+      duration:     info.duration,
+      mbid:         None,
+      mtime:        info.mtime,
+      indexed_hash: None,
+      file_size:    info.file_size,
+
+      release_id:       None,
+      release_group_id: None,
+      medium_position:  None,
+      track_position:   None,
+      release_date:     None,
+
+      tagged_mbid:             info.tagged_mbid,
+      tagged_release_id:       info.tagged_release_id,
+      tagged_release_group_id: info.tagged_release_group_id,
+      isrc:                    info.isrc,
+      fingerprint_mbid:        None,
+
+      album_artist:   info.album_artist,
+      disc_number:    info.disc_number,
+      disc_total:     info.disc_total,
+      track_total:    info.track_total,
+      date:           info.date,
+      original_date:  info.original_date,
+      genres:         info.genres,
+      composer:       info.composer,
+      label:          info.label,
+      catalog_number: info.catalog_number,
+      comment:        info.comment,
+
+      container_format: info.container_format,
+      codec:            info.codec,
+      codec_profile:    info.codec_profile,
+      lossless:         info.lossless,
+      bitrate:          info.bitrate,
+      vbr:              info.vbr,
+      sample_rate:      info.sample_rate,
+      bit_depth:        info.bit_depth,
+      channels:         info.channels,
+      channel_layout:   info.channel_layout,
+      encoder:          info.encoder,
+      encoder_settings: info.encoder_settings,
+
+      metadata_version: info.metadata_version,
     }
   }
 }
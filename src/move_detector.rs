@@ -0,0 +1,153 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
+
+use models::{MediaFileInfo, NewMediaFileInfo};
+
+// Whether a newly found file has the same contents as an existing entry,
+// going by its size, duration and tags
+//
+// Entries added before the file size was recorded match on the rest.
+fn is_same_file(info: &NewMediaFileInfo, db_info: &MediaFileInfo) -> bool {
+  let same_size = match (info.file_size, db_info.file_size) {
+    (Some(a), Some(b)) => a == b,
+    _ => true,
+  };
+
+  same_size &&
+  info.duration     == db_info.duration &&
+  info.track_number == db_info.track_number &&
+  info.title        == db_info.title &&
+  info.artist       == db_info.artist &&
+  info.album        == db_info.album &&
+  info.track        == db_info.track
+}
+
+// Library entries whose files were not found during a scan
+//
+// New files are matched against these before being inserted, so a file that
+// was moved or renamed keeps its entry (and with it the MusicBrainz ID and
+// AcoustID check history) instead of being inserted again while the old entry
+// is left for `prune`.
+pub struct MoveDetector {
+  missing: RefCell<HashMap<i32, String>>,
+  moved: Cell<usize>,
+}
+
+impl MoveDetector {
+  pub fn new(missing: HashMap<i32, String>) -> Rc<Self> {
+    Rc::new(Self {
+      missing: RefCell::new(missing),
+      moved: Cell::new(0),
+    })
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.missing.borrow().is_empty()
+  }
+
+  // Number of missing entries not matched to a new file
+  pub fn remaining(&self) -> usize {
+    self.missing.borrow().len()
+  }
+
+  // Number of entries matched to a new file
+  pub fn moved(&self) -> usize {
+    self.moved.get()
+  }
+
+  // Pick the missing entry the new file was moved from out of `candidates`
+  //
+  // The entry is only claimed when exactly one missing entry matches, an
+  // ambiguous match is treated as a new file.
+  pub fn claim(&self, info: &NewMediaFileInfo, candidates: Vec<MediaFileInfo>) -> Option<MediaFileInfo> {
+    let mut missing = self.missing.borrow_mut();
+
+    let mut matches: Vec<MediaFileInfo> = candidates.into_iter()
+      .filter(|db_info| missing.contains_key(&db_info.id))
+      .filter(|db_info| is_same_file(info, db_info))
+      // The file may have come back since the scan started
+      .filter(|db_info| !Path::new(&db_info.path).exists())
+      .collect();
+
+    if matches.len() != 1 {
+      if matches.len() > 1 {
+        debug!("path: {}, {} possible previous locations, treating as new file", info.path, matches.len());
+      }
+
+      return None;
+    }
+
+    let db_info = matches.remove(0);
+    missing.remove(&db_info.id);
+    self.moved.set(self.moved.get() + 1);
+
+    Some(db_info)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use chrono::{TimeZone, Utc};
+
+  fn new_info(path: &str) -> NewMediaFileInfo {
+    NewMediaFileInfo {
+      file_size:    Some(4_000_000),
+      title:        Some("Title".to_owned()),
+      artist:       Some("Artist".to_owned()),
+      album:        Some("Album".to_owned()),
+      track:        Some("Title".to_owned()),
+      track_number: 3,
+      duration:     215_000,
+      ..NewMediaFileInfo::new(path, Utc.timestamp(1_517_700_000, 0))
+    }
+  }
+
+  fn db_info(id: i32, path: &str) -> MediaFileInfo {
+    MediaFileInfo::test_info(id, new_info(path))
+  }
+
+  #[test]
+  fn test_claim_single_match() {
+    let mut missing = HashMap::new();
+    missing.insert(1, "/nonexistent/old/a.flac".to_owned());
+    let moves = MoveDetector::new(missing);
+
+    let info = new_info("/nonexistent/new/a.flac");
+    let candidates = vec![db_info(1, "/nonexistent/old/a.flac"), db_info(2, "/nonexistent/other/a.flac")];
+
+    let claimed = moves.claim(&info, candidates).unwrap();
+    assert_eq!(claimed.id, 1);
+    assert_eq!(moves.moved(), 1);
+    assert!(moves.is_empty());
+  }
+
+  #[test]
+  fn test_claim_ambiguous() {
+    let mut missing = HashMap::new();
+    missing.insert(1, "/nonexistent/old/a.flac".to_owned());
+    missing.insert(2, "/nonexistent/old/b.flac".to_owned());
+    let moves = MoveDetector::new(missing);
+
+    let info = new_info("/nonexistent/new/a.flac");
+    let candidates = vec![db_info(1, "/nonexistent/old/a.flac"), db_info(2, "/nonexistent/old/b.flac")];
+
+    assert!(moves.claim(&info, candidates).is_none());
+    assert_eq!(moves.remaining(), 2);
+  }
+
+  #[test]
+  fn test_claim_different_size() {
+    let mut missing = HashMap::new();
+    missing.insert(1, "/nonexistent/old/a.flac".to_owned());
+    let moves = MoveDetector::new(missing);
+
+    let mut info = new_info("/nonexistent/new/a.mp3");
+    info.file_size = Some(1_000_000);
+
+    assert!(moves.claim(&info, vec![db_info(1, "/nonexistent/old/a.flac")]).is_none());
+  }
+}
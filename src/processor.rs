@@ -1,29 +1,240 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::mem;
 use std::path::Path;
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
+use std::sync::mpsc;
 
 use futures::{Future, Stream};
 use futures::{future, stream};
 use futures_cpupool::{Builder as CpuPoolBuilder, CpuPool};
-use tokio_core::reactor::Core;
+use notify::{self, RecursiveMode, Watcher};
+use tokio_core::reactor::{Core, Interval};
 
-use acoustid::AcoustId;
-use config::Config;
+use acoustid::{self, AcoustId};
+use bulk_indexer::{BulkFlush, BulkIndexer};
+use config::{AcoustIdConfig, ConcurrencyConfig, Config, SearchConfig, WatchConfig};
 use database::DatabaseConnection;
 use elasticsearch::ElasticSearch;
 use scanner;
 use file_processor::FileProcessor;
+use metadata::MetadataReaders;
+use models::{ExistingFile, MediaFileInfo, NewScanFailure};
+use move_detector::MoveDetector;
+use rate_limiter::RateLimitMetrics;
+use response_cache::ResponseCache;
+use watcher::WatchBatch;
 
 use basic_types::*;
 
+// Number of library entries read from the database and sent in each bulk
+// request while rebuilding the search index
+static REINDEX_PAGE_SIZE: i64 = 500;
+
+// Tally of the files handled by a scan
+#[derive(Debug, Default)]
+pub struct ScanSummary {
+  pub processed: usize,
+  pub unchanged: usize,
+  pub skipped: usize,
+  pub moved: usize,
+  pub missing: usize,
+  pub indexed: usize,
+  pub failures: Vec<NewScanFailure>,
+
+  // AcoustID requests sent and time spent waiting on the rate limit
+  pub acoustid: RateLimitMetrics,
+}
+
+impl ScanSummary {
+  pub fn print(&self) {
+    println!("Processed {} files, {} unchanged, skipped {} files, {} failures", self.processed, self.unchanged, self.skipped, self.failures.len());
+
+    if self.moved > 0 {
+      println!("{} entries were moved or renamed", self.moved);
+    }
+
+    if self.missing > 0 {
+      println!("{} entries no longer exist on disk, run prune to remove them", self.missing);
+    }
+
+    println!("Sent {} changed documents to Elasticsearch", self.indexed);
+
+    let waited = self.acoustid.waited;
+    let waited = waited.as_secs() as f64 + f64::from(waited.subsec_nanos()) / 1e9;
+    println!("Sent {} requests to AcoustID, waited {:.1} seconds for the rate limit", self.acoustid.requests, waited);
+
+    for failure in &self.failures {
+      println!("  [{}] {}: {}: {}", failure.phase, failure.kind, failure.path, failure.message);
+    }
+  }
+}
+
+// What is known about the library ahead of processing a set of files
+struct Preloaded {
+  states: HashMap<String, ExistingFile>,
+  moves: Rc<MoveDetector>,
+}
+
+// Events driving the scan pipeline. Timer ticks are merged in with the
+// processed files so a partial batch of search documents is still sent when
+// files are slow to process.
+enum ScanEvent {
+  File(String, Result<MediaFileInfo, ProcessorError>),
+  Tick,
+  Done,
+}
+
+// State shared by the steps of the scan pipeline that run on the reactor
+#[derive(Clone)]
+struct ScanContext {
+  conn: Arc<DatabaseConnection>,
+  indexer: Rc<BulkIndexer>,
+  previous_failures: Rc<HashSet<String>>,
+  summary: Rc<RefCell<ScanSummary>>,
+}
+
+impl ScanContext {
+  fn handle_file(&self, path: String, res: Result<MediaFileInfo, ProcessorError>) -> Box<Future<Item = (), Error = ProcessorError>> {
+    let info = match res {
+      Ok(info) => info,
+      Err(err) => {
+        match *err.root() {
+          ProcessorError::NothingUseful => {
+            self.summary.borrow_mut().skipped += 1;
+            return self.clear_failure(path);
+          },
+          ProcessorError::Unchanged => {
+            self.summary.borrow_mut().unchanged += 1;
+            return self.clear_failure(path);
+          },
+          _ => {},
+        };
+
+        error!("path: {}, failed: {}", path, err);
+        return self.record_failure(NewScanFailure::from_error(&path, &err));
+      },
+    };
+
+    self.summary.borrow_mut().processed += 1;
+
+    // Skip sending the document when it is identical to what was last indexed
+    let doc = info.to_document();
+    if info.indexed_hash == Some(doc.content_hash()) {
+      return self.clear_failure(path);
+    }
+
+    let context = self.clone();
+    let future = self.clear_failure(path)
+      .and_then(move |_| {
+        let flush = context.indexer.push(doc);
+        context.handle_index_flush(flush)
+      });
+
+    Box::new(future)
+  }
+
+  // Store the hashes of the indexed documents and record the documents a bulk
+  // request failed to index as scan failures
+  fn handle_index_flush(
+    &self,
+    flush: Box<Future<Item = BulkFlush, Error = ProcessorError>>
+  ) -> Box<Future<Item = (), Error = ProcessorError>> {
+    let context = self.clone();
+
+    let future = flush.and_then(move |BulkFlush { indexed, failures }| {
+      context.summary.borrow_mut().indexed += indexed.len();
+
+      let update_hashes = context.conn.update_indexed_hashes(indexed)
+        .or_else(|e| {
+          error!("error updating indexed hashes: {:#?}", e);
+          Ok(())
+        });
+
+      let futures: Vec<_> = failures.into_iter()
+        .map(|failure| {
+          error!("id: {}, path: {}, failed to index: {}", failure.id, failure.path, failure.message);
+
+          {
+            let mut summary = context.summary.borrow_mut();
+            summary.processed = summary.processed.saturating_sub(1);
+          }
+
+          context.record_failure(NewScanFailure::new(&failure.path, ScanPhase::Search, "elastic", failure.message))
+        })
+        .collect();
+
+      update_hashes
+        .join(future::join_all(futures))
+        .map(|_| ())
+    });
+
+    Box::new(future)
+  }
+
+  fn record_failure(&self, failure: NewScanFailure) -> Box<Future<Item = (), Error = ProcessorError>> {
+    self.summary.borrow_mut().failures.push(failure.clone());
+
+    let path = failure.path.clone();
+    let future = self.conn.add_scan_failure(failure)
+      .or_else(move |e| {
+        error!("error recording scan failure for path = {}: {:#?}", path, e);
+        Ok(())
+      });
+
+    Box::new(future)
+  }
+
+  // Drop the old failure record of a file that no longer fails
+  fn clear_failure(&self, path: String) -> Box<Future<Item = (), Error = ProcessorError>> {
+    if !self.previous_failures.contains(&path) {
+      return Box::new(future::ok(()));
+    }
+
+    let future = self.conn.delete_scan_failure(path.clone())
+      .or_else(move |e| {
+        error!("error clearing scan failure for path = {}: {:#?}", path, e);
+        Ok(())
+      });
+
+    Box::new(future)
+  }
+}
+
+// Remove a library entry whose file is gone, along with its AcoustID check
+// history and search document
+fn delete_entry(conn: &Arc<DatabaseConnection>, search: &Arc<ElasticSearch>, id: i32) -> impl Future<Item = (), Error = ProcessorError> {
+  let conn2 = Arc::clone(conn);
+  let search = Arc::clone(search);
+
+  conn.delete_acoustid_last_check(id)
+    .and_then(move |_| conn2.delete_file(id))
+    .map_err(ProcessorError::from)
+    .and_then(move |_| search.delete_document(id).map_err(ProcessorError::from))
+    .and_then(move |_| {
+      info!("id: {} deleted", id);
+      Ok(())
+    })
+    .map_err(move |e| {
+      error!("error deleting id = {}: {:#?}", id, e);
+      e
+    })
+}
+
 pub struct Processor<'a> {
   paths: &'a Vec<String>,
+  concurrency: &'a ConcurrencyConfig,
+  search_config: &'a SearchConfig,
+  watch_config: &'a WatchConfig,
+  acoustid_config: &'a AcoustIdConfig,
 
   core: Core,
-  thread_pool: CpuPool,
+  metadata_pool: CpuPool,
 
   acoustid: Arc<AcoustId>,
   conn: Arc<DatabaseConnection>,
+  metadata: Arc<MetadataReaders>,
   search: Arc<ElasticSearch>,
 }
 
@@ -31,32 +242,63 @@ impl<'a> Processor<'a> {
   pub fn new(config: &'a Config) -> Self {
     let api_key = config.api_keys.get("acoustid").expect("Failed to get Acoustid API key");
 
+    let concurrency = &config.concurrency;
+
     let mut core = Core::new().unwrap();
     let thread_pool = CpuPoolBuilder::new()
       .name_prefix("pool_thread")
       .create();
 
-    let acoustid = Arc::new(AcoustId::new(api_key.clone(), thread_pool.clone(), &core.handle()));
-    let conn = Arc::new(DatabaseConnection::new(thread_pool.clone()));
-    let search = Arc::new(ElasticSearch::new(thread_pool.clone(), &core.handle()));
+    // Separate pools for each phase of processing a file so each phase is
+    // bounded by its own limit from the configuration
+    let metadata_pool = CpuPoolBuilder::new()
+      .pool_size(concurrency.metadata)
+      .name_prefix("metadata_thread")
+      .create();
+    let fingerprint_pool = CpuPoolBuilder::new()
+      .pool_size(concurrency.fingerprint)
+      .name_prefix("fingerprint_thread")
+      .create();
+    let database_pool = CpuPoolBuilder::new()
+      .pool_size(concurrency.writes)
+      .name_prefix("database_thread")
+      .create();
+
+    let limiter = acoustid::rate_limiter(&config.acoustid, &core.handle());
+    let conn = Arc::new(DatabaseConnection::new(database_pool, concurrency.writes as u32));
+    let cache = ResponseCache::new(Arc::clone(&conn), &config.acoustid);
+    let acoustid = Arc::new(AcoustId::new(api_key.clone(), &config.acoustid, &limiter, cache, fingerprint_pool, &core.handle()));
+    let metadata = Arc::new(MetadataReaders::new(&config.metadata));
+    let search = Arc::new(ElasticSearch::new(thread_pool, &core.handle()));
 
     let future = search.ensure_index_exists();
     core.run(future).expect("Failed to create Elasticsearch index");
 
     Self {
       paths: &config.paths,
+      concurrency,
+      search_config: &config.search,
+      watch_config: &config.watch,
+      acoustid_config: &config.acoustid,
 
       core,
-      thread_pool,
+      metadata_pool,
 
       acoustid,
       conn,
+      metadata,
       search,
     }
   }
 
+  // Look up every file on AcoustID instead of using cached responses
+  pub fn bypass_cache(&self) {
+    self.acoustid.bypass_cache();
+  }
+
   pub fn prune_db(&mut self) -> Result<(), ProcessorError> {
     let conn = Arc::clone(&self.conn);
+    let search = Arc::clone(&self.search);
     let futures = Rc::new(Mutex::new(Vec::new()));
 
     let futures2 = Rc::clone(&futures);
@@ -66,73 +308,376 @@ impl<'a> Processor<'a> {
       if !path.exists() {
         println!("id: {}, path: {:?}", id, path);
 
-        let conn2 = Arc::clone(&conn);
-
-        let future = conn.delete_acoustid_last_check(id)
-          .and_then(move |_| conn2.delete_file(id))
-          .and_then(move |_| {
-            info!("id: {} deleted", id);
-            Ok(())
-          })
-          .map_err(move |e| {
-            error!("error deleting id = {}: {:#?}", id, e);
-            ProcessorError::NothingUseful
-          });
-
-        futures2.lock().unwrap().push(future);
+        futures2.lock().unwrap().push(delete_entry(&conn, &search, id));
       }
     };
 
     try!(self.conn.path_iter(cb));
 
+    // A failed delete does not stop the others, the search document of an
+    // entry whose row was already deleted is left for `--reconcile`
     let mut futures = futures.lock().unwrap();
-    let futures: Vec<_> = futures.drain(..).collect();
-    try!(self.core.run(future::join_all(futures)));
+    let futures: Vec<_> = futures.drain(..)
+      .map(|future| future.then(|res| -> Result<bool, ProcessorError> { Ok(res.is_ok()) }))
+      .collect();
+    let results = try!(self.core.run(future::join_all(futures)));
+
+    let failed = results.iter().filter(|&&deleted| !deleted).count();
+    if failed > 0 {
+      println!("{} entries could not be removed, see the log", failed);
+    }
 
     Ok(())
   }
 
-  pub fn scan_dirs(&mut self) -> Result<Box<i32>, ProcessorError> {
-    for path in self.paths {
-      println!("Scanning {}", path);
+  // Remove documents from the search index that no longer have a
+  // corresponding library entry
+  pub fn reconcile_search(&mut self) -> Result<(), ProcessorError> {
+    let library_ids: HashSet<i32> = try!(self.core.run(self.conn.get_ids()))
+      .into_iter()
+      .collect();
+    let document_ids = try!(self.core.run(self.search.document_ids()));
 
-      let dir_walk = scanner::scan_dir(path);
-      let files: Vec<String> = dir_walk.to_vec();
+    let orphans: Vec<i32> = document_ids.into_iter()
+      .filter(|id| !library_ids.contains(id))
+      .collect();
+
+    println!("Removing {} orphaned search documents", orphans.len());
 
-      debug!("files length: {}", files.len());
+    let search = Arc::clone(&self.search);
+    let handler = stream::iter_ok::<_, ProcessorError>(orphans).map(move |id| {
+      search.delete_document(id)
+        .and_then(move |_| {
+          info!("id: {} removed from search index", id);
+          Ok(())
+        })
+        .or_else(move |e| {
+          error!("error removing id = {} from search index: {:#?}", id, e);
+          Ok(())
+        })
+    })
+    .buffer_unordered(self.concurrency.writes)
+    .for_each(|_| Ok(()));
+
+    self.core.run(handler)
+  }
 
-      let thread_pool = self.thread_pool.clone();
+  // Rebuild the search index from the database into a new versioned index,
+  // then move the alias over to it once every entry has been indexed
+  pub fn reindex(&mut self) -> Result<(), ProcessorError> {
+    let index = try!(self.core.run(self.search.create_next_index()));
+    println!("Reindexing into {}", index);
 
-      let acoustid = Arc::clone(&self.acoustid);
-      let conn = Arc::clone(&self.conn);
-      let search = Arc::clone(&self.search);
+    let conn = Arc::clone(&self.conn);
+    let conn2 = Arc::clone(&self.conn);
+    let search = Arc::clone(&self.search);
+    let index2 = index.clone();
 
-      let handler = stream::iter_ok(files).and_then(move |file| {
-        let thread_pool = thread_pool.clone();
-        let worker = FileProcessor::new(&acoustid, &conn, thread_pool);
-        worker.call(file)
-      }).and_then(move |info| {
-        let doc = info.to_document();
+    // `None` as the state ends the stream after the last page
+    let pages = stream::unfold(Some(0), move |last_id| {
+      last_id.map(|last_id| {
+        conn.fetch_files_after(last_id, REINDEX_PAGE_SIZE)
+          .map_err(ProcessorError::from)
+          .map(|files| {
+            let next = if (files.len() as i64) < REINDEX_PAGE_SIZE {
+              None
+            } else {
+              files.last().map(|info| info.id)
+            };
 
-        search.insert_document(doc)
-          .map_err(|e| {
-            error!("elastic error: {:#?}", e);
-            ProcessorError::NothingUseful
+            (files, next)
           })
-          .and_then(|res| {
-            trace!("elastic insert res: {:?}", res);
-            Ok(())
+      })
+    });
+
+    let handler = pages
+      .filter(|files| !files.is_empty())
+      .and_then(move |files| {
+        let docs: Vec<_> = files.iter().map(|info| info.to_document()).collect();
+        let count = docs.len();
+
+        let conn = Arc::clone(&conn2);
+
+        search.bulk_index(&index2, &docs)
+          .map_err(ProcessorError::from)
+          .and_then(move |res| {
+            let errors = ElasticSearch::bulk_item_errors(&res);
+            for &(id, ref message) in &errors {
+              error!("id: {}, failed to index: {}", id, message);
+            }
+
+            let hashes = docs.iter()
+              .filter(|doc| !errors.iter().any(|&(id, _)| id == doc.id))
+              .map(|doc| (doc.id, doc.content_hash()))
+              .collect();
+
+            conn.update_indexed_hashes(hashes)
+              .map_err(ProcessorError::from)
+              .map(move |_| count)
           })
-      }).or_else(|err| match err {
-        ProcessorError::NothingUseful => Ok(()),
-        _ => Err(err),
-      }).for_each(|_| {
-        Ok(())
+      })
+      .fold(0, |total, count| -> Result<usize, ProcessorError> {
+        let total = total + count;
+        info!("{} entries indexed", total);
+
+        Ok(total)
+      });
+
+    let total = try!(self.core.run(handler));
+    println!("Indexed {} entries into {}", total, index);
+
+    let old = try!(self.core.run(self.search.swap_alias(index)));
+    for name in old {
+      println!("Removing old index {}", name);
+      try!(self.core.run(self.search.delete_index(&name)));
+    }
+
+    Ok(())
+  }
+
+  // Load the paths of files that failed during a previous scan so their
+  // failure records can be cleared once they are processed successfully
+  fn previous_failures(&mut self) -> Result<Rc<HashSet<String>>, ProcessorError> {
+    let failures = try!(self.core.run(self.conn.fetch_scan_failures()));
+    let paths = failures.into_iter().map(|failure| failure.path).collect();
+
+    Ok(Rc::new(paths))
+  }
+
+  // Run files through the scan pipeline, recording any file that fails in
+  // the summary and the `scan_failures` table instead of aborting
+  fn process_files(
+    &mut self,
+    files: Vec<String>,
+    preloaded: Option<Preloaded>,
+    previous_failures: &Rc<HashSet<String>>,
+    summary: &Rc<RefCell<ScanSummary>>
+  ) -> Result<(), ProcessorError> {
+    let metadata_pool = self.metadata_pool.clone();
+    let min_score = self.acoustid_config.min_score;
+
+    let acoustid = Arc::clone(&self.acoustid);
+    let conn = Arc::clone(&self.conn);
+    let metadata = Arc::clone(&self.metadata);
+
+    let indexer = BulkIndexer::new(&self.search, self.search_config.bulk_size, self.search_config.bulk_interval());
+    let context = ScanContext {
+      conn: Arc::clone(&self.conn),
+      indexer: Rc::clone(&indexer),
+      previous_failures: Rc::clone(previous_failures),
+      summary: Rc::clone(summary),
+    };
+
+    // Each file is still processed in order by its own future chain, the
+    // buffering only allows several of those chains to run at once.
+    //
+    // Each file is looked up in the preloaded states when there are any,
+    // otherwise its entry is fetched from the database
+    let mut preloaded = preloaded;
+    let files = stream::iter_ok::<_, ProcessorError>(files).map(move |file| {
+      let worker = FileProcessor::new(&acoustid, &conn, &metadata, min_score, metadata_pool.clone());
+      let path = file.clone();
+
+      let future = match preloaded {
+        Some(ref mut preloaded) => {
+          let state = preloaded.states.remove(&file);
+
+          worker
+            .with_moves(&preloaded.moves)
+            .call_with_state(file, state)
+        },
+        None => worker.call(file),
+      };
+
+      future.then(move |res| Ok(ScanEvent::File(path, res)))
+    })
+    .buffer_unordered(self.concurrency.files_in_flight())
+    .chain(stream::once(Ok(ScanEvent::Done)));
+
+    let ticks = try!(Interval::new(self.search_config.bulk_interval(), &self.core.handle()))
+      .map(|_| ScanEvent::Tick)
+      .map_err(ProcessorError::from);
+
+    let context2 = context.clone();
+    let handler = files.select(ticks)
+      .take_while(|event| match *event {
+        ScanEvent::Done => Ok(false),
+        _ => Ok(true),
+      })
+      .for_each(move |event| -> Box<Future<Item = (), Error = ProcessorError>> {
+        match event {
+          ScanEvent::File(path, res) => context2.handle_file(path, res),
+          ScanEvent::Tick => context2.handle_index_flush(context2.indexer.flush_stale()),
+          ScanEvent::Done => Box::new(future::ok(())),
+        }
       });
 
-      self.core.run(handler).unwrap();
+    try!(self.core.run(handler));
+
+    // Send whatever is left in the last partial batch
+    let future = context.handle_index_flush(indexer.flush());
+    self.core.run(future)
+  }
+
+  pub fn scan_dirs(&mut self) -> Result<ScanSummary, ProcessorError> {
+    let previous_failures = try!(self.previous_failures());
+    let summary = Rc::new(RefCell::new(ScanSummary::default()));
+    let metrics = self.acoustid.rate_limit_metrics();
+
+    // Walk every path and load the state of its entries up front. Entries
+    // without a file on disk are missing, and are matched against new files
+    // in any of the paths to find files that were moved.
+    let mut roots = Vec::new();
+    let mut missing = HashMap::new();
+
+    let paths = self.paths;
+    for path in paths {
+      let dir_walk = scanner::scan_dir(path);
+      debug!("path: {}, files length: {}", path, dir_walk.len());
+
+      let states = try!(self.conn.load_root_state(path));
+      {
+        let walked: HashSet<&String> = dir_walk.iter().collect();
+
+        for (file, state) in &states {
+          if !walked.contains(file) {
+            debug!("id: {}, path: {}, missing", state.id, file);
+            missing.insert(state.id, file.clone());
+          }
+        }
+      }
+
+      roots.push((path, dir_walk, states));
+    }
+
+    let moves = MoveDetector::new(missing);
+
+    for (path, dir_walk, states) in roots {
+      println!("Scanning {}", path);
+
+      let preloaded = Preloaded {
+        states,
+        moves: Rc::clone(&moves),
+      };
+
+      try!(self.process_files(dir_walk, Some(preloaded), &previous_failures, &summary));
     }
 
-    Ok(Box::new(9000))
+    {
+      let mut summary = summary.borrow_mut();
+      summary.moved = moves.moved();
+      summary.missing = moves.remaining();
+      summary.acoustid = self.acoustid.rate_limit_metrics().since(&metrics);
+    }
+
+    let summary = mem::replace(&mut *summary.borrow_mut(), ScanSummary::default());
+    Ok(summary)
+  }
+
+  // Run individual files through the scan pipeline, fetching each entry from
+  // the database
+  fn process_paths(&mut self, files: Vec<String>) -> Result<ScanSummary, ProcessorError> {
+    let previous_failures = try!(self.previous_failures());
+    let summary = Rc::new(RefCell::new(ScanSummary::default()));
+    let metrics = self.acoustid.rate_limit_metrics();
+
+    try!(self.process_files(files, None, &previous_failures, &summary));
+    summary.borrow_mut().acoustid = self.acoustid.rate_limit_metrics().since(&metrics);
+
+    let summary = mem::replace(&mut *summary.borrow_mut(), ScanSummary::default());
+    Ok(summary)
+  }
+
+  // Reprocess only the files recorded in `scan_failures`
+  pub fn retry_failures(&mut self) -> Result<ScanSummary, ProcessorError> {
+    let files: Vec<String> = try!(self.previous_failures()).iter().cloned().collect();
+    println!("Retrying {} failed files", files.len());
+
+    self.process_paths(files)
+  }
+
+  // Keep the library up to date with changes to the configured paths as they
+  // happen, until the watcher stops
+  pub fn watch(&mut self) -> Result<(), ProcessorError> {
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = try!(notify::watcher(tx, self.watch_config.delay()));
+
+    let paths = self.paths;
+    for path in paths {
+      try!(watcher.watch(path, RecursiveMode::Recursive));
+      println!("Watching {}", path);
+    }
+
+    // Wait for the next event, then take every other event that has already
+    // arrived so a burst of changes is handled together
+    while let Ok(event) = rx.recv() {
+      let mut batch = WatchBatch::default();
+      batch.add(event);
+
+      while let Ok(event) = rx.try_recv() {
+        batch.add(event);
+      }
+
+      if !batch.is_empty() {
+        try!(self.handle_watch_batch(batch));
+      }
+    }
+
+    Ok(())
+  }
+
+  fn handle_watch_batch(&mut self, batch: WatchBatch) -> Result<(), ProcessorError> {
+    let WatchBatch { renamed, mut removed, changed, rescan } = batch;
+
+    if rescan {
+      warn!("filesystem events were dropped, rescanning every path");
+      return self.scan_dirs().map(|summary| summary.print());
+    }
+
+    // Move the entries of renamed files along with them. The new paths are
+    // also in `changed`, so they are reindexed under their new path.
+    for (from, to) in renamed {
+      match self.core.run(self.conn.rename_path(from.clone(), to.clone())) {
+        Ok(count) => info!("renamed {} entries: {} -> {}", count, from, to),
+        Err(e) => {
+          error!("error renaming {} -> {}: {:#?}", from, to, e);
+          removed.insert(from);
+        },
+      };
+    }
+
+    let mut ids = Vec::new();
+    for path in removed {
+      // The file may have come back since the event was sent
+      if Path::new(&path).exists() {
+        continue;
+      }
+
+      let states = try!(self.conn.load_root_state(&path));
+      ids.extend(states.values().map(|state| state.id));
+
+      if let Some(info) = try!(self.core.run(self.conn.fetch_file(path))) {
+        ids.push(info.id);
+      }
+    }
+
+    if !ids.is_empty() {
+      println!("Removing {} entries", ids.len());
+
+      let futures: Vec<_> = ids.into_iter()
+        .map(|id| {
+          delete_entry(&self.conn, &self.search, id)
+            .or_else(|_| -> Result<(), ProcessorError> { Ok(()) })
+        })
+        .collect();
+      try!(self.core.run(future::join_all(futures)));
+    }
+
+    if !changed.is_empty() {
+      let summary = try!(self.process_paths(changed.into_iter().collect()));
+      summary.print();
+    }
+
+    Ok(())
   }
 }
@@ -0,0 +1,156 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use futures::Future;
+use futures::future;
+use tokio_core::reactor::{Handle, Timeout};
+
+use basic_types::*;
+
+// Totals of the requests let through a rate limiter
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RateLimitMetrics {
+  pub requests: u64,
+
+  // Time requests spent waiting for their turn, added up
+  pub waited: Duration,
+}
+
+impl RateLimitMetrics {
+  // Requests and waiting since an earlier snapshot
+  pub fn since(&self, earlier: &RateLimitMetrics) -> RateLimitMetrics {
+    RateLimitMetrics {
+      requests: self.requests - earlier.requests,
+      waited: self.waited - earlier.waited,
+    }
+  }
+}
+
+// Token bucket holding up to `burst` tokens, refilled at `rate` tokens per
+// second
+//
+// A request that finds the bucket empty takes a token anyway and waits until
+// the bucket would have refilled it, so requests are let through in the
+// order they arrived without polling.
+struct TokenBucket {
+  rate: f64,
+  burst: f64,
+  tokens: f64,
+  last: Instant,
+
+  metrics: RateLimitMetrics,
+}
+
+impl TokenBucket {
+  fn new(rate: f64, burst: f64, now: Instant) -> Self {
+    Self {
+      rate,
+      burst,
+      tokens: burst,
+      last: now,
+
+      metrics: RateLimitMetrics::default(),
+    }
+  }
+
+  // Take a token, returning how long to wait before sending the request
+  fn reserve(&mut self, now: Instant) -> Duration {
+    let elapsed = now.duration_since(self.last);
+    let elapsed = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+
+    self.tokens = (self.tokens + elapsed * self.rate).min(self.burst) - 1.0;
+    self.last = now;
+
+    let wait = if self.tokens >= 0.0 {
+      Duration::from_secs(0)
+    } else {
+      let secs = -self.tokens / self.rate;
+      Duration::new(secs.trunc() as u64, (secs.fract() * 1e9) as u32)
+    };
+
+    self.metrics.requests += 1;
+    self.metrics.waited += wait;
+
+    wait
+  }
+}
+
+// Rate limit driven by timers on the reactor
+//
+// Clones share the same bucket, so one limiter created per process covers
+// every request made with it.
+#[derive(Clone)]
+pub struct RateLimiter {
+  bucket: Rc<RefCell<TokenBucket>>,
+  handle: Handle,
+}
+
+impl RateLimiter {
+  // Allow `rate` requests per second, with up to `burst` sent at once
+  pub fn new(rate: f64, burst: u32, handle: &Handle) -> Self {
+    let bucket = TokenBucket::new(rate, f64::from(burst), Instant::now());
+
+    Self {
+      bucket: Rc::new(RefCell::new(bucket)),
+      handle: handle.clone(),
+    }
+  }
+
+  // Resolves once a request may be sent
+  pub fn wait(&self) -> Box<Future<Item = (), Error = ProcessorError>> {
+    let wait = self.bucket.borrow_mut().reserve(Instant::now());
+    if wait == Duration::from_secs(0) {
+      return Box::new(future::ok(()));
+    }
+
+    debug!("waiting {:?} for rate limit", wait);
+
+    match Timeout::new(wait, &self.handle) {
+      Ok(timeout) => Box::new(timeout.map_err(ProcessorError::from)),
+      Err(err) => Box::new(future::err(ProcessorError::from(err))),
+    }
+  }
+
+  pub fn metrics(&self) -> RateLimitMetrics {
+    self.bucket.borrow().metrics
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + u64::from(duration.subsec_nanos()) / 1_000_000
+  }
+
+  #[test]
+  fn test_burst_then_spaced() {
+    let start = Instant::now();
+    let mut bucket = TokenBucket::new(4.0, 2.0, start);
+
+    assert_eq!(millis(bucket.reserve(start)), 0);
+    assert_eq!(millis(bucket.reserve(start)), 0);
+    assert_eq!(millis(bucket.reserve(start)), 250);
+    assert_eq!(millis(bucket.reserve(start)), 500);
+
+    assert_eq!(bucket.metrics.requests, 4);
+    assert_eq!(millis(bucket.metrics.waited), 750);
+  }
+
+  #[test]
+  fn test_refill_up_to_burst() {
+    let start = Instant::now();
+    let mut bucket = TokenBucket::new(4.0, 2.0, start);
+
+    bucket.reserve(start);
+    bucket.reserve(start);
+
+    // A long pause only refills the bucket up to the burst size
+    let later = start + Duration::from_secs(10);
+    assert_eq!(millis(bucket.reserve(later)), 0);
+    assert_eq!(millis(bucket.reserve(later)), 0);
+    assert_eq!(millis(bucket.reserve(later)), 250);
+  }
+}
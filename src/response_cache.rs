@@ -0,0 +1,127 @@
+use std::cell::Cell;
+use std::sync::Arc;
+
+use chrono::{Duration, Utc};
+use futures::Future;
+use futures::future;
+use futures_cpupool::Builder as CpuPoolBuilder;
+use serde_json;
+use tokio_core::reactor::Core;
+
+use config::{AcoustIdConfig, Config};
+use database::DatabaseConnection;
+use models::{self, CachedResponse};
+
+use basic_types::*;
+
+// AcoustID lookup results stored in the `acoustid_responses` table
+//
+// The same audio gives the same fingerprint across copies and rescans, so a
+// response is keyed by the fingerprint, its duration and the metadata
+// requested, and reused until it is older than the configured TTL.
+pub struct ResponseCache {
+  conn: Arc<DatabaseConnection>,
+  ttl: Duration,
+  meta: String,
+
+  // Set to skip cached responses, new responses are still stored
+  bypass: Cell<bool>,
+}
+
+impl ResponseCache {
+  // `None` if the configured TTL disables the cache
+  pub fn new(conn: Arc<DatabaseConnection>, config: &AcoustIdConfig) -> Option<Self> {
+    if config.cache_ttl == 0 {
+      return None;
+    }
+
+    Some(Self {
+      conn,
+      ttl: Duration::days(config.cache_ttl as i64),
+      meta: config.meta_param(),
+
+      bypass: Cell::new(false),
+    })
+  }
+
+  pub fn set_bypass(&self, bypass: bool) {
+    self.bypass.set(bypass);
+  }
+
+  fn key(fingerprint: &str, duration: f64, meta: &str) -> i64 {
+    let data = format!("{}\n{:.0}\n{}", fingerprint, duration, meta);
+
+    models::stable_hash(data.as_bytes())
+  }
+
+  // Results of an earlier lookup of the fingerprint that have not expired
+  pub fn fetch(&self, fingerprint: &str, duration: f64) -> Box<Future<Item = Option<Vec<AcoustIdResult>>, Error = ProcessorError>> {
+    if self.bypass.get() {
+      return Box::new(future::ok(None));
+    }
+
+    let key = Self::key(fingerprint, duration, &self.meta);
+    let fresh_after = Utc::now() - self.ttl;
+
+    let future = self.conn.fetch_cached_response(key, fresh_after)
+      .map_err(ProcessorError::from)
+      .and_then(|response| match response {
+        Some(response) => serde_json::from_value(response.results)
+          .map(Some)
+          .map_err(ProcessorError::from),
+        None => Ok(None),
+      });
+
+    Box::new(future)
+  }
+
+  pub fn store(&self, fingerprint: &str, duration: f64, results: &[AcoustIdResult]) -> Box<Future<Item = (), Error = ProcessorError>> {
+    let results = match serde_json::to_value(results) {
+      Ok(v) => v,
+      Err(err) => return Box::new(future::err(ProcessorError::from(err))),
+    };
+
+    let response = CachedResponse {
+      key: Self::key(fingerprint, duration, &self.meta),
+      results,
+      fetched_at: Utc::now(),
+    };
+
+    Box::new(self.conn.save_cached_response(response).map_err(ProcessorError::from))
+  }
+}
+
+// Delete cached responses older than the configured TTL, returning the number
+// deleted
+//
+// Only the database is used. With the cache disabled every response is
+// deleted.
+pub fn purge_stale(config: &Config) -> Result<usize, ProcessorError> {
+  let mut core = try!(Core::new());
+  let database_pool = CpuPoolBuilder::new()
+    .pool_size(1)
+    .name_prefix("database_thread")
+    .create();
+
+  let conn = DatabaseConnection::new(database_pool, 1);
+  let before = Utc::now() - Duration::days(config.acoustid.cache_ttl as i64);
+
+  core.run(conn.purge_cached_responses(before)).map_err(ProcessorError::from)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_key() {
+    let key = ResponseCache::key("AQAAAoEA", 215.4, "recordings+releases");
+
+    // Durations are compared in whole seconds
+    assert_eq!(key, ResponseCache::key("AQAAAoEA", 215.0, "recordings+releases"));
+
+    assert_ne!(key, ResponseCache::key("AQAAAoEB", 215.0, "recordings+releases"));
+    assert_ne!(key, ResponseCache::key("AQAAAoEA", 216.0, "recordings+releases"));
+    assert_ne!(key, ResponseCache::key("AQAAAoEA", 215.0, "recordings+releasegroups"));
+  }
+}
@@ -0,0 +1,72 @@
+use std::sync::Arc;
+
+use futures_cpupool::Builder as CpuPoolBuilder;
+use tokio_core::reactor::Core;
+use uuid::Uuid;
+
+use config::Config;
+use database::DatabaseConnection;
+use models::{AcoustIdCandidate, MediaFileInfo, ReviewStatus};
+
+use basic_types::*;
+
+// Decisions on library entries whose AcoustID candidates were not a clear
+// enough match to be stored during a scan
+//
+// Only the database is used, so reviewing works without AcoustID or
+// Elasticsearch. Entries given a MusicBrainz ID are reindexed by the next
+// scan.
+pub struct Reviewer {
+  core: Core,
+  conn: Arc<DatabaseConnection>,
+}
+
+impl Reviewer {
+  pub fn new(config: &Config) -> Result<Self, ProcessorError> {
+    let core = try!(Core::new());
+    let database_pool = CpuPoolBuilder::new()
+      .pool_size(1)
+      .name_prefix("database_thread")
+      .create();
+
+    Ok(Self {
+      core,
+      conn: Arc::new(DatabaseConnection::new(database_pool, config.concurrency.writes as u32)),
+    })
+  }
+
+  pub fn pending(&mut self) -> Result<Vec<MediaFileInfo>, ProcessorError> {
+    self.core.run(self.conn.fetch_pending_reviews()).map_err(ProcessorError::from)
+  }
+
+  pub fn candidates(&mut self, id: i32) -> Result<Vec<AcoustIdCandidate>, ProcessorError> {
+    self.core.run(self.conn.fetch_acoustid_candidates(id)).map_err(ProcessorError::from)
+  }
+
+  // Entries tagged with a different recording than AcoustID matched their
+  // fingerprint to
+  pub fn disagreements(&mut self) -> Result<Vec<MediaFileInfo>, ProcessorError> {
+    self.core.run(self.conn.fetch_mbid_disagreements()).map_err(ProcessorError::from)
+  }
+
+  // Store one of the entry's candidate recordings
+  pub fn accept(&mut self, id: i32, mbid: Uuid) -> Result<(), ProcessorError> {
+    self.decide(id, ReviewStatus::Accepted, Some(mbid))
+  }
+
+  // Leave the entry without a MusicBrainz ID
+  pub fn reject(&mut self, id: i32) -> Result<(), ProcessorError> {
+    self.decide(id, ReviewStatus::Rejected, None)
+  }
+
+  // Store a MusicBrainz ID that was not among the candidates
+  pub fn set_manual(&mut self, id: i32, mbid: Uuid) -> Result<(), ProcessorError> {
+    self.decide(id, ReviewStatus::Manual, Some(mbid))
+  }
+
+  fn decide(&mut self, id: i32, decision: ReviewStatus, mbid: Option<Uuid>) -> Result<(), ProcessorError> {
+    info!("id: {}, review {}, mbid: {:?}", id, decision.as_str(), mbid);
+
+    self.core.run(self.conn.decide_review(id, decision, mbid)).map_err(ProcessorError::from)
+  }
+}
@@ -1,3 +1,21 @@
+table! {
+    acoustid_candidates (id) {
+        id -> Int4,
+        library_id -> Int4,
+        acoustid_id -> Varchar,
+        score -> Float4,
+        recording_id -> Uuid,
+        title -> Nullable<Varchar>,
+        artists -> Nullable<Varchar>,
+        duration -> Nullable<Int4>,
+        release_id -> Nullable<Uuid>,
+        release_group_id -> Nullable<Uuid>,
+        medium_position -> Nullable<Int4>,
+        track_position -> Nullable<Int4>,
+        release_date -> Nullable<Varchar>,
+    }
+}
+
 table! {
     acoustid_last_checks (id) {
         id -> Int4,
@@ -6,6 +24,48 @@ table! {
     }
 }
 
+table! {
+    acoustid_responses (key) {
+        key -> Int8,
+        results -> Jsonb,
+        fetched_at -> Timestamptz,
+    }
+}
+
+table! {
+    acoustid_reviews (library_id) {
+        library_id -> Int4,
+        status -> Varchar,
+        mbid -> Nullable<Uuid>,
+        created_at -> Timestamptz,
+        decided_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    acoustid_submissions (library_id) {
+        library_id -> Int4,
+        submission_id -> Int8,
+        mbid -> Uuid,
+        status -> Varchar,
+        acoustid_id -> Nullable<Varchar>,
+        submitted_at -> Timestamptz,
+        checked_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    fingerprints (library_id) {
+        library_id -> Int4,
+        fingerprint -> Varchar,
+        raw -> Array<Int4>,
+        duration -> Float8,
+        algorithm -> Int4,
+        version -> Varchar,
+        mtime -> Timestamptz,
+    }
+}
+
 table! {
     library (id) {
         id -> Int4,
@@ -18,12 +78,69 @@ table! {
         duration -> Oid,
         mbid -> Nullable<Uuid>,
         mtime -> Timestamptz,
+        indexed_hash -> Nullable<Int8>,
+        file_size -> Nullable<Int8>,
+        release_id -> Nullable<Uuid>,
+        release_group_id -> Nullable<Uuid>,
+        medium_position -> Nullable<Int4>,
+        track_position -> Nullable<Int4>,
+        release_date -> Nullable<Varchar>,
+        tagged_mbid -> Nullable<Uuid>,
+        tagged_release_id -> Nullable<Uuid>,
+        tagged_release_group_id -> Nullable<Uuid>,
+        isrc -> Nullable<Varchar>,
+        fingerprint_mbid -> Nullable<Uuid>,
+        album_artist -> Nullable<Varchar>,
+        disc_number -> Nullable<Int4>,
+        disc_total -> Nullable<Int4>,
+        track_total -> Nullable<Int4>,
+        date -> Nullable<Varchar>,
+        original_date -> Nullable<Varchar>,
+        genres -> Array<Text>,
+        composer -> Nullable<Varchar>,
+        label -> Nullable<Varchar>,
+        catalog_number -> Nullable<Varchar>,
+        comment -> Nullable<Varchar>,
+        container_format -> Nullable<Varchar>,
+        codec -> Nullable<Varchar>,
+        codec_profile -> Nullable<Varchar>,
+        lossless -> Nullable<Bool>,
+        bitrate -> Nullable<Int4>,
+        vbr -> Nullable<Bool>,
+        sample_rate -> Nullable<Int4>,
+        bit_depth -> Nullable<Int4>,
+        channels -> Nullable<Int4>,
+        channel_layout -> Nullable<Varchar>,
+        encoder -> Nullable<Varchar>,
+        encoder_settings -> Nullable<Varchar>,
+        metadata_version -> Int4,
+    }
+}
+
+table! {
+    scan_failures (id) {
+        id -> Int4,
+        path -> Varchar,
+        phase -> Varchar,
+        kind -> Varchar,
+        message -> Varchar,
+        failed_at -> Timestamptz,
     }
 }
 
+joinable!(acoustid_candidates -> library (library_id));
 joinable!(acoustid_last_checks -> library (library_id));
+joinable!(acoustid_reviews -> library (library_id));
+joinable!(acoustid_submissions -> library (library_id));
+joinable!(fingerprints -> library (library_id));
 
 allow_tables_to_appear_in_same_query!(
+    acoustid_candidates,
     acoustid_last_checks,
+    acoustid_responses,
+    acoustid_reviews,
+    acoustid_submissions,
+    fingerprints,
     library,
+    scan_failures,
 );
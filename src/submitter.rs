@@ -0,0 +1,235 @@
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::{Future, Stream};
+use futures::{future, stream};
+use futures_cpupool::{Builder as CpuPoolBuilder, CpuPool};
+use tokio_core::reactor::{Core, Timeout};
+use uuid::Uuid;
+
+use acoustid::{self, AcoustId, AcoustIdClient, FingerprintSubmission};
+use config::Config;
+use database::DatabaseConnection;
+use fingerprint;
+use metadata::{MetadataReaders, METADATA_VERSION};
+use models::{MediaFileInfo, NewAcoustIdSubmission};
+use rate_limiter::RateLimiter;
+
+use basic_types::*;
+
+// How many times pending submissions are checked on, and the seconds waited
+// before each check
+static STATUS_CHECKS: u32 = 12;
+static STATUS_INTERVAL: u64 = 5;
+
+// Tally of a `submit` run
+#[derive(Debug, Default)]
+pub struct SubmitSummary {
+  // Files unknown to AcoustID without a MusicBrainz ID tag
+  pub untagged: usize,
+
+  pub submitted: usize,
+  pub imported: usize,
+
+  // Submissions AcoustID has not imported yet, checked on again by the next
+  // run
+  pub pending: usize,
+}
+
+// Submits fingerprints of files AcoustID did not know, along with the
+// MusicBrainz recording ID they are tagged with
+//
+// Only the database and AcoustID are used. Every submission is recorded in
+// the `acoustid_submissions` table, so no file is submitted twice.
+pub struct Submitter<'a> {
+  config: &'a Config,
+  api_key: String,
+  user_key: String,
+
+  core: Core,
+  metadata_pool: CpuPool,
+  fingerprint_pool: CpuPool,
+
+  client: Rc<AcoustIdClient>,
+  limiter: RateLimiter,
+  conn: Arc<DatabaseConnection>,
+  metadata: Arc<MetadataReaders>,
+}
+
+impl<'a> Submitter<'a> {
+  pub fn new(config: &'a Config, api_key: &str, user_key: &str) -> Result<Self, ProcessorError> {
+    let concurrency = &config.concurrency;
+
+    let core = try!(Core::new());
+    let metadata_pool = CpuPoolBuilder::new()
+      .pool_size(concurrency.metadata)
+      .name_prefix("metadata_thread")
+      .create();
+    let fingerprint_pool = CpuPoolBuilder::new()
+      .pool_size(concurrency.fingerprint)
+      .name_prefix("fingerprint_thread")
+      .create();
+    let database_pool = CpuPoolBuilder::new()
+      .pool_size(concurrency.writes)
+      .name_prefix("database_thread")
+      .create();
+
+    let client = Rc::new(acoustid::client(&config.acoustid, 1, &core.handle()));
+    let limiter = acoustid::rate_limiter(&config.acoustid, &core.handle());
+    let conn = Arc::new(DatabaseConnection::new(database_pool, concurrency.writes as u32));
+    let metadata = Arc::new(MetadataReaders::new(&config.metadata));
+
+    Ok(Self {
+      config,
+      api_key: api_key.to_owned(),
+      user_key: user_key.to_owned(),
+
+      core,
+      metadata_pool,
+      fingerprint_pool,
+
+      client,
+      limiter,
+      conn,
+      metadata,
+    })
+  }
+
+  pub fn run(&mut self) -> Result<SubmitSummary, ProcessorError> {
+    let mut summary = SubmitSummary::default();
+
+    let files = try!(self.core.run(self.conn.fetch_unknown_files()));
+    println!("{} files are unknown to AcoustID", files.len());
+
+    let tagged = try!(self.gather(files));
+    summary.untagged = tagged.iter().filter(|submission| submission.is_none()).count();
+
+    let tagged: Vec<(i32, FingerprintSubmission)> = tagged.into_iter()
+      .filter_map(|submission| submission)
+      .collect();
+
+    for chunk in tagged.chunks(self.config.acoustid.batch_size) {
+      summary.submitted += try!(self.submit(chunk));
+    }
+
+    summary.imported = try!(self.check_pending());
+    summary.pending = try!(self.core.run(self.conn.fetch_pending_submissions())).len();
+
+    Ok(summary)
+  }
+
+  // Tagged MusicBrainz ID and fingerprint of each file, `None` for files
+  // without the tag
+  fn gather(&mut self, files: Vec<MediaFileInfo>) -> Result<Vec<Option<(i32, FingerprintSubmission)>>, ProcessorError> {
+    let metadata_pool = self.metadata_pool.clone();
+    let fingerprint_pool = self.fingerprint_pool.clone();
+    let conn = Arc::clone(&self.conn);
+    let metadata = Arc::clone(&self.metadata);
+
+    let future = stream::iter_ok::<_, ProcessorError>(files).map(move |info| {
+      let fingerprint_pool = fingerprint_pool.clone();
+      let conn = Arc::clone(&conn);
+      let metadata = Arc::clone(&metadata);
+      let path = info.path.clone();
+      let path2 = info.path.clone();
+
+      // Tags are only read again for entries read by an older version, which
+      // may not have their tagged IDs stored
+      let tagged_mbid: Box<Future<Item = Option<Uuid>, Error = ProcessorError>> = if info.metadata_version < METADATA_VERSION {
+        Box::new(metadata_pool.spawn_fn(move || Ok(metadata.read(&path).and_then(|tags| tags.tagged_mbid))))
+      } else {
+        Box::new(future::ok(info.tagged_mbid))
+      };
+
+      tagged_mbid
+        .and_then(move |mbid| -> Box<Future<Item = Option<(i32, FingerprintSubmission)>, Error = ProcessorError>> {
+          let mbid: Uuid = match mbid {
+            Some(v) => v,
+            None => return Box::new(future::ok(None)),
+          };
+
+          let id = info.id;
+
+          Box::new(
+            fingerprint::get_stored(&fingerprint_pool, &conn, id, &info.path, info.mtime)
+              .map(move |(duration, fingerprint)| Some((id, FingerprintSubmission { duration, fingerprint, mbid })))
+          )
+        })
+        .or_else(move |e| {
+          error!("path: {}, unable to prepare submission: {}", path2, e);
+          Ok(None)
+        })
+    })
+    .buffer_unordered(self.config.concurrency.fingerprint)
+    .collect();
+
+    self.core.run(future)
+  }
+
+  // Submit a batch of fingerprints and record the submissions, resolving to
+  // the number submitted
+  fn submit(&mut self, batch: &[(i32, FingerprintSubmission)]) -> Result<usize, ProcessorError> {
+    let submissions: Vec<FingerprintSubmission> = batch.iter().map(|&(_, ref submission)| submission.clone()).collect();
+
+    let future = AcoustId::submit(&self.api_key, &self.user_key, &self.config.acoustid, &self.limiter, &self.client, &submissions);
+    let results = try!(self.core.run(future));
+
+    let records: Vec<NewAcoustIdSubmission> = results.into_iter()
+      .filter_map(|result| {
+        let &(library_id, ref submission) = batch.get(result.index()?)?;
+        debug!("id: {}, submitted as {} with mbid: {}", library_id, result.id, submission.mbid);
+
+        Some(NewAcoustIdSubmission {
+          library_id,
+          submission_id: result.id,
+          mbid: submission.mbid,
+          status: result.status,
+        })
+      })
+      .collect();
+
+    let count = records.len();
+    try!(self.core.run(self.conn.add_submissions(records)));
+
+    Ok(count)
+  }
+
+  // Check on pending submissions until AcoustID has imported them or the
+  // checks run out, resolving to the number imported
+  fn check_pending(&mut self) -> Result<usize, ProcessorError> {
+    let mut imported = 0;
+
+    for _ in 0..STATUS_CHECKS {
+      let pending = try!(self.core.run(self.conn.fetch_pending_submissions()));
+      if pending.is_empty() {
+        break;
+      }
+
+      println!("Waiting for AcoustID to import {} submissions", pending.len());
+
+      let timeout = try!(Timeout::new(Duration::from_secs(STATUS_INTERVAL), &self.core.handle()));
+      try!(self.core.run(timeout));
+
+      let ids: Vec<i64> = pending.iter().map(|submission| submission.submission_id).collect();
+      for chunk in ids.chunks(self.config.acoustid.batch_size) {
+        let future = AcoustId::submission_status(&self.api_key, &self.config.acoustid, &self.limiter, &self.client, chunk);
+
+        for status in try!(self.core.run(future)) {
+          if status.is_pending() {
+            continue;
+          }
+
+          if status.status == "imported" {
+            imported += 1;
+          }
+
+          let track_id = status.result.map(|result| result.id);
+          try!(self.core.run(self.conn.update_submission_status(status.id, status.status, track_id)));
+        }
+      }
+    }
+
+    Ok(imported)
+  }
+}
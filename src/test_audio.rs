@@ -0,0 +1,315 @@
+// Short synthetic recordings in the formats the scanner reads, written to the
+// temporary directory so readers and fingerprinting can be tested without
+// shipping audio files
+
+use std::env;
+use std::f64::consts::PI;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
+
+// Seconds of audio in each recording, enough for Chromaprint to produce a
+// fingerprint
+pub static DURATION: u32 = 4;
+
+pub static PCM_SAMPLE_RATE: u32 = 11_025;
+pub static DSD_SAMPLE_RATE: u32 = 2_822_400;
+
+pub static TITLE: &'static str = "Synthetic Tone";
+pub static DESCRIPTION: &'static str = "Broadcast Wave test recording";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+  Wav,
+  // Broadcast Wave, a WAV file with a `bext` chunk
+  Bwf,
+  Aiff,
+  Dsf,
+  Dff,
+}
+
+pub static FORMATS: &'static [Format] = &[
+  Format::Wav,
+  Format::Bwf,
+  Format::Aiff,
+  Format::Dsf,
+  Format::Dff,
+];
+
+// Formats that need their encoders to write, found in `tests/fixtures/audio`
+// and written by `tests/fixtures/audio/generate.sh`
+pub static ENCODED_EXTENSIONS: &'static [&'static str] = &[
+  "ape",
+  "wv",
+  "tta",
+];
+
+pub fn encoded_fixture(extension: &str) -> String {
+  format!("{}/tests/fixtures/audio/tone.{}", env!("CARGO_MANIFEST_DIR"), extension)
+}
+
+impl Format {
+  pub fn extension(&self) -> &'static str {
+    match *self {
+      Format::Wav | Format::Bwf => "wav",
+      Format::Aiff => "aiff",
+      Format::Dsf => "dsf",
+      Format::Dff => "dff",
+    }
+  }
+
+  pub fn sample_rate(&self) -> u32 {
+    match *self {
+      Format::Dsf | Format::Dff => DSD_SAMPLE_RATE,
+      _ => PCM_SAMPLE_RATE,
+    }
+  }
+
+  pub fn codec(&self) -> &'static str {
+    match *self {
+      Format::Dsf | Format::Dff => "DSD",
+      _ => "PCM",
+    }
+  }
+
+  // Whether the recording is written with a title tag
+  pub fn has_title(&self) -> bool {
+    *self != Format::Dff
+  }
+}
+
+// A recording in the temporary directory, removed when dropped
+pub struct Recording {
+  path: PathBuf,
+}
+
+impl Recording {
+  // `name` keeps recordings of tests running at the same time apart
+  pub fn write(format: Format, name: &str) -> Self {
+    let file_name = format!("catalog-{}-{:?}.{}", name, format, format.extension());
+    let path = env::temp_dir().join(file_name.to_lowercase());
+
+    let data = match format {
+      Format::Wav => wav(false),
+      Format::Bwf => wav(true),
+      Format::Aiff => aiff(),
+      Format::Dsf => dsf(),
+      Format::Dff => dff(),
+    };
+
+    File::create(&path)
+      .and_then(|mut file| file.write_all(&data))
+      .expect("unable to write recording");
+
+    Recording { path }
+  }
+
+  pub fn path(&self) -> &str {
+    self.path.to_str().expect("temporary directory is not valid UTF-8")
+  }
+}
+
+impl Drop for Recording {
+  fn drop(&mut self) {
+    let _ = fs::remove_file(&self.path);
+  }
+}
+
+// Level of the signal at `time` seconds, a different note every half second
+// so the pitches Chromaprint looks at change over the recording
+fn signal(time: f64) -> f64 {
+  let notes = [440.0, 523.25, 659.25, 392.0, 587.33, 493.88, 349.23, 783.99];
+  let note = notes[(time * 2.0) as usize % notes.len()];
+
+  0.4 * (2.0 * PI * note * time).sin() + 0.2 * (4.0 * PI * note * time).sin()
+}
+
+fn pcm_samples() -> Vec<i16> {
+  (0..PCM_SAMPLE_RATE * DURATION)
+    .map(|i| (signal(f64::from(i) / f64::from(PCM_SAMPLE_RATE)) * f64::from(i16::max_value())) as i16)
+    .collect()
+}
+
+// One bit samples from a first order sigma-delta modulator, packed with the
+// first sample of each byte in its most or least significant bit
+fn dsd_samples(msb_first: bool) -> Vec<u8> {
+  let samples = DSD_SAMPLE_RATE * DURATION;
+  let mut bytes = vec![0; (samples / 8) as usize];
+  let mut integrator = 0.0;
+
+  for i in 0..samples {
+    let output = if integrator >= 0.0 { 1.0 } else { -1.0 };
+    integrator += signal(f64::from(i) / f64::from(DSD_SAMPLE_RATE)) - output;
+
+    if output > 0.0 {
+      let bit = if msb_first { 7 - i % 8 } else { i % 8 };
+      bytes[(i / 8) as usize] |= 1 << bit;
+    }
+  }
+
+  bytes
+}
+
+fn le(value: u64, size: usize) -> Vec<u8> {
+  (0..size).map(|i| (value >> (8 * i)) as u8).collect()
+}
+
+fn be(value: u64, size: usize) -> Vec<u8> {
+  (0..size).rev().map(|i| (value >> (8 * i)) as u8).collect()
+}
+
+// A chunk with a size field of `size_bytes` bytes not counting the header,
+// padded to an even length
+fn chunk(id: &[u8], data: &[u8], size: fn(u64, usize) -> Vec<u8>, size_bytes: usize) -> Vec<u8> {
+  let mut chunk = id.to_vec();
+  chunk.extend(size(data.len() as u64, size_bytes));
+  chunk.extend(data);
+
+  if data.len() % 2 == 1 {
+    chunk.push(0);
+  }
+
+  chunk
+}
+
+fn wav(bext: bool) -> Vec<u8> {
+  let mut fmt = le(1, 2);
+  fmt.extend(le(1, 2));
+  fmt.extend(le(u64::from(PCM_SAMPLE_RATE), 4));
+  fmt.extend(le(u64::from(PCM_SAMPLE_RATE) * 2, 4));
+  fmt.extend(le(2, 2));
+  fmt.extend(le(16, 2));
+
+  let mut info = b"INFO".to_vec();
+  info.extend(chunk(b"INAM", format!("{}\0", TITLE).as_bytes(), le, 4));
+
+  let mut data = Vec::new();
+  for sample in pcm_samples() {
+    data.extend(le(sample as u16 as u64, 2));
+  }
+
+  let mut body = b"WAVE".to_vec();
+
+  // Description, originator, dates and the rest of version 0 of the fixed
+  // size part of the chunk
+  if bext {
+    let mut description = DESCRIPTION.as_bytes().to_vec();
+    description.resize(602, 0);
+    body.extend(chunk(b"bext", &description, le, 4));
+  }
+
+  body.extend(chunk(b"fmt ", &fmt, le, 4));
+  body.extend(chunk(b"LIST", &info, le, 4));
+  body.extend(chunk(b"data", &data, le, 4));
+
+  chunk(b"RIFF", &body, le, 4)
+}
+
+// 80 bit extended precision float used for AIFF sample rates
+fn extended(value: u32) -> Vec<u8> {
+  let exponent = 31 - value.leading_zeros();
+
+  let mut bytes = be(16_383 + u64::from(exponent), 2);
+  bytes.extend(be(u64::from(value) << (63 - exponent), 8));
+  bytes
+}
+
+fn aiff() -> Vec<u8> {
+  let samples = pcm_samples();
+
+  let mut comm = be(1, 2);
+  comm.extend(be(samples.len() as u64, 4));
+  comm.extend(be(16, 2));
+  comm.extend(extended(PCM_SAMPLE_RATE));
+
+  // Offset and block size of the samples
+  let mut ssnd = be(0, 4);
+  ssnd.extend(be(0, 4));
+  for sample in samples {
+    ssnd.extend(be(sample as u16 as u64, 2));
+  }
+
+  let mut body = b"AIFF".to_vec();
+  body.extend(chunk(b"COMM", &comm, be, 4));
+  body.extend(chunk(b"NAME", TITLE.as_bytes(), be, 4));
+  body.extend(chunk(b"SSND", &ssnd, be, 4));
+
+  chunk(b"FORM", &body, be, 4)
+}
+
+// ID3v2.3 tag holding the title, as DSF files carry their tags
+fn id3() -> Vec<u8> {
+  let mut frame = b"TIT2".to_vec();
+  frame.extend(be(TITLE.len() as u64 + 1, 4));
+  frame.extend(&[0, 0, 0]);
+  frame.extend(TITLE.as_bytes());
+
+  // The size is stored 7 bits per byte
+  let size = frame.len() as u64;
+  let mut tag = b"ID3".to_vec();
+  tag.extend(&[3, 0, 0]);
+  tag.extend((0..4).rev().map(|i| ((size >> (7 * i)) & 0x7f) as u8));
+  tag.extend(frame);
+  tag
+}
+
+fn dsf() -> Vec<u8> {
+  // Samples are stored in blocks of 4096 bytes per channel, the last one
+  // padded
+  let mut data = dsd_samples(false);
+  let padded = (data.len() + 4095) / 4096 * 4096;
+  data.resize(padded, 0);
+
+  let tag = id3();
+
+  // Chunk sizes of DSF files count the chunk header
+  let metadata_offset = 28 + 52 + 12 + data.len() as u64;
+
+  let mut file = b"DSD ".to_vec();
+  file.extend(le(28, 8));
+  file.extend(le(metadata_offset + tag.len() as u64, 8));
+  file.extend(le(metadata_offset, 8));
+
+  // Format version and ID, mono, one channel, sample rate, bits per sample,
+  // sample count and block size
+  file.extend(b"fmt ");
+  file.extend(le(52, 8));
+  file.extend(le(1, 4));
+  file.extend(le(0, 4));
+  file.extend(le(1, 4));
+  file.extend(le(1, 4));
+  file.extend(le(u64::from(DSD_SAMPLE_RATE), 4));
+  file.extend(le(1, 4));
+  file.extend(le(u64::from(DSD_SAMPLE_RATE * DURATION), 8));
+  file.extend(le(4096, 4));
+  file.extend(le(0, 4));
+
+  file.extend(b"data");
+  file.extend(le(12 + data.len() as u64, 8));
+  file.extend(data);
+
+  file.extend(tag);
+  file
+}
+
+fn dff() -> Vec<u8> {
+  let mut channels = be(1, 2);
+  channels.extend(b"C   ");
+
+  let mut compression = b"DSD ".to_vec();
+  compression.push(14);
+  compression.extend(b"not compressed");
+  compression.push(0);
+
+  let mut properties = b"SND ".to_vec();
+  properties.extend(chunk(b"FS  ", &be(u64::from(DSD_SAMPLE_RATE), 4), be, 8));
+  properties.extend(chunk(b"CHNL", &channels, be, 8));
+  properties.extend(chunk(b"CMPR", &compression, be, 8));
+
+  let mut body = b"DSD ".to_vec();
+  body.extend(chunk(b"FVER", &be(0x0105_0000, 4), be, 8));
+  body.extend(chunk(b"PROP", &properties, be, 8));
+  body.extend(chunk(b"DSD ", &dsd_samples(true), be, 8));
+
+  chunk(b"FRM8", &body, be, 8)
+}
@@ -0,0 +1,147 @@
+// Local HTTP stand-in for the AcoustID web service, replaying recorded
+// responses so lookups can be tested without network access
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use flate2::read::GzDecoder;
+
+use config::AcoustIdConfig;
+
+// A request received by the stand-in, with a gzip compressed body already
+// decompressed
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+  pub method: String,
+  pub path: String,
+  pub body: String,
+}
+
+pub struct TestServer {
+  port: u16,
+  requests: Arc<Mutex<Vec<RecordedRequest>>>,
+}
+
+// Contents of a recorded response in `tests/fixtures/acoustid`
+pub fn fixture(name: &str) -> String {
+  let path = format!("{}/tests/fixtures/acoustid/{}", env!("CARGO_MANIFEST_DIR"), name);
+
+  let mut contents = String::new();
+  ::std::fs::File::open(&path)
+    .and_then(|mut file| file.read_to_string(&mut contents))
+    .expect("unable to read fixture");
+
+  contents
+}
+
+impl TestServer {
+  // Answer each request with the next of `responses`, a status code and
+  // body, closing the connection after every response
+  pub fn start(responses: Vec<(u16, String)>) -> Self {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("unable to bind test server");
+    let port = listener.local_addr().unwrap().port();
+
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let requests2 = Arc::clone(&requests);
+
+    thread::Builder::new()
+      .name("acoustid_test_server".into())
+      .spawn(move || {
+        for (status, body) in responses {
+          let (stream, _) = match listener.accept() {
+            Ok(v) => v,
+            Err(_) => return,
+          };
+
+          let mut reader = BufReader::new(stream);
+          let request = match read_request(&mut reader) {
+            Some(v) => v,
+            None => return,
+          };
+          requests2.lock().unwrap().push(request);
+
+          let response = format!("HTTP/1.1 {} Test\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+          );
+
+          let mut stream = reader.into_inner();
+          let _ = stream.write_all(response.as_bytes());
+          let _ = stream.flush();
+        }
+      })
+      .unwrap();
+
+    Self {
+      port,
+      requests,
+    }
+  }
+
+  // Configuration pointing lookups at the stand-in, sending every lookup
+  // in batches of `batch_size`
+  pub fn config(&self, batch_size: usize) -> AcoustIdConfig {
+    AcoustIdConfig {
+      batch_size,
+      url: format!("http://127.0.0.1:{}/v2", self.port),
+      tls: false,
+      ..AcoustIdConfig::default()
+    }
+  }
+
+  pub fn requests(&self) -> Vec<RecordedRequest> {
+    self.requests.lock().unwrap().clone()
+  }
+}
+
+fn read_request<R: BufRead>(reader: &mut R) -> Option<RecordedRequest> {
+  let mut line = String::new();
+  if reader.read_line(&mut line).ok()? == 0 {
+    return None;
+  }
+
+  let mut parts = line.split_whitespace();
+  let method = parts.next()?.to_owned();
+  let path = parts.next()?.to_owned();
+
+  let mut content_length = 0;
+  let mut gzip = false;
+  loop {
+    let mut header = String::new();
+    reader.read_line(&mut header).ok()?;
+
+    let header = header.trim();
+    if header.is_empty() {
+      break;
+    }
+
+    let mut parts = header.splitn(2, ':');
+    let name = parts.next()?.trim().to_lowercase();
+    let value = parts.next().unwrap_or("").trim();
+
+    if name == "content-length" {
+      content_length = value.parse().ok()?;
+    } else if name == "content-encoding" {
+      gzip = value == "gzip";
+    }
+  }
+
+  let mut data = vec![0; content_length];
+  reader.read_exact(&mut data).ok()?;
+
+  let mut body = String::new();
+  if gzip {
+    GzDecoder::new(&data[..]).read_to_string(&mut body).ok()?;
+  } else {
+    body = String::from_utf8(data).ok()?;
+  }
+
+  Some(RecordedRequest {
+    method,
+    path,
+    body,
+  })
+}
@@ -0,0 +1,129 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use notify::DebouncedEvent;
+
+use scanner;
+
+fn path_string(path: &Path) -> Option<String> {
+  let path = path.to_str().map(|s| s.to_owned());
+  if path.is_none() {
+    warn!("ignoring change to path that is not valid UTF-8");
+  }
+
+  path
+}
+
+fn is_hidden(path: &Path) -> bool {
+  path.file_name()
+    .and_then(|name| name.to_str())
+    .map(|s| s.starts_with('.'))
+    .unwrap_or(false)
+}
+
+// Changes to the library collected from a burst of filesystem events
+//
+// Renames are applied to the database first so the renamed files keep their
+// entries, then the removed entries are deleted and the remaining changed
+// files are run through `FileProcessor`.
+#[derive(Debug, Default)]
+pub struct WatchBatch {
+  pub renamed: Vec<(String, String)>,
+  pub removed: BTreeSet<String>,
+  pub changed: BTreeSet<String>,
+
+  // Events were dropped by the kernel, so only a full scan can catch up
+  pub rescan: bool,
+}
+
+impl WatchBatch {
+  pub fn is_empty(&self) -> bool {
+    self.renamed.is_empty() && self.removed.is_empty() && self.changed.is_empty() && !self.rescan
+  }
+
+  pub fn add(&mut self, event: DebouncedEvent) {
+    trace!("watch event: {:?}", event);
+
+    match event {
+      DebouncedEvent::Create(path) | DebouncedEvent::Write(path) => self.add_changed(&path),
+      DebouncedEvent::Remove(path) => {
+        if let Some(path) = path_string(&path) {
+          self.changed.remove(&path);
+          self.removed.insert(path);
+        }
+      },
+      DebouncedEvent::Rename(from, to) => {
+        if let (Some(from), Some(to)) = (path_string(&from), path_string(&to)) {
+          self.changed.remove(&from);
+          self.renamed.push((from, to));
+        }
+
+        self.add_changed(&to);
+      },
+      DebouncedEvent::Rescan => self.rescan = true,
+      DebouncedEvent::Error(err, path) => error!("watch error for path = {:?}: {}", path, err),
+      DebouncedEvent::NoticeWrite(_) |
+      DebouncedEvent::NoticeRemove(_) |
+      DebouncedEvent::Chmod(_) => {},
+    };
+  }
+
+  // A directory that appears counts as every file inside it changing
+  fn add_changed(&mut self, path: &Path) {
+    if is_hidden(path) {
+      return;
+    }
+
+    let path = match path_string(path) {
+      Some(v) => v,
+      None => return,
+    };
+
+    let files = if Path::new(&path).is_dir() {
+      scanner::scan_dir(&path)
+    } else {
+      vec![path]
+    };
+
+    for file in files {
+      self.removed.remove(&file);
+      self.changed.insert(file);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use std::path::PathBuf;
+
+  #[test]
+  fn test_batch_latest_event_wins() {
+    let mut batch = WatchBatch::default();
+
+    batch.add(DebouncedEvent::Write(PathBuf::from("/nonexistent/a.flac")));
+    batch.add(DebouncedEvent::Remove(PathBuf::from("/nonexistent/a.flac")));
+    batch.add(DebouncedEvent::Remove(PathBuf::from("/nonexistent/b.flac")));
+    batch.add(DebouncedEvent::Create(PathBuf::from("/nonexistent/b.flac")));
+    batch.add(DebouncedEvent::Create(PathBuf::from("/nonexistent/.hidden.flac")));
+    batch.add(DebouncedEvent::Chmod(PathBuf::from("/nonexistent/c.flac")));
+
+    assert!(batch.removed.contains("/nonexistent/a.flac"));
+    assert!(!batch.changed.contains("/nonexistent/a.flac"));
+    assert!(batch.changed.contains("/nonexistent/b.flac"));
+    assert!(!batch.removed.contains("/nonexistent/b.flac"));
+    assert_eq!(batch.changed.len(), 1);
+    assert!(!batch.rescan);
+  }
+
+  #[test]
+  fn test_batch_rename() {
+    let mut batch = WatchBatch::default();
+
+    batch.add(DebouncedEvent::Rename(PathBuf::from("/nonexistent/old.flac"), PathBuf::from("/nonexistent/new.flac")));
+
+    assert_eq!(batch.renamed, vec![("/nonexistent/old.flac".to_owned(), "/nonexistent/new.flac".to_owned())]);
+    assert!(batch.changed.contains("/nonexistent/new.flac"));
+  }
+}
@@ -0,0 +1,18 @@
+#!/bin/sh
+# Writes the Monkey's Audio, WavPack and TTA recordings read by the metadata
+# and fingerprinting tests
+#
+# Needs ffmpeg and the `mac` encoder from Monkey's Audio.
+
+set -e
+
+cd "$(dirname "$0")"
+
+ffmpeg -y -loglevel error -f lavfi -i "sine=frequency=440:sample_rate=11025:duration=4" \
+  -ac 1 -c:a pcm_s16le tone.wav
+
+ffmpeg -y -loglevel error -i tone.wav -c:a wavpack tone.wv
+ffmpeg -y -loglevel error -i tone.wav -c:a tta tone.tta
+mac tone.wav tone.ape -c2000
+
+rm tone.wav